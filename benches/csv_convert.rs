@@ -0,0 +1,69 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rcli::{
+    process_csv_parallel, process_csv_stream, CsvDialect, CsvQuery, CsvTypes, OutputFormat,
+};
+
+const ROWS: usize = 1_000_000;
+
+// 生成和 juventus.csv 结构一样的 100 万行数据
+fn generate() -> Vec<u8> {
+    let mut input = String::from("Name,Position,DOB,Nationality,Kit Number\n");
+    for i in 0..ROWS {
+        input.push_str(&format!(
+            "Player {},Midfielder,\"Jan 1, 1990 (30)\",Italy,{}\n",
+            i,
+            i % 99
+        ));
+    }
+    input.into_bytes()
+}
+
+fn bench_convert(c: &mut Criterion) {
+    let input = generate();
+    let dialect = CsvDialect::default();
+    let types = CsvTypes {
+        infer: true,
+        ..Default::default()
+    };
+    let query = CsvQuery::default();
+
+    let mut group = c.benchmark_group("csv_1m_rows");
+    group.sample_size(10);
+    for format in [OutputFormat::Json, OutputFormat::Csv] {
+        group.bench_function(BenchmarkId::new("sequential", format), |b| {
+            b.iter(|| {
+                let mut output = Vec::with_capacity(input.len() * 3);
+                process_csv_stream(
+                    &mut input.as_slice(),
+                    &mut output,
+                    format,
+                    &dialect,
+                    &types,
+                    &query,
+                )
+                .unwrap();
+                output
+            })
+        });
+        group.bench_function(BenchmarkId::new("parallel", format), |b| {
+            b.iter(|| {
+                let mut output = Vec::with_capacity(input.len() * 3);
+                process_csv_parallel(
+                    &mut input.as_slice(),
+                    &mut output,
+                    format,
+                    &dialect,
+                    &types,
+                    &query,
+                    0,
+                )
+                .unwrap();
+                output
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_convert);
+criterion_main!(benches);
@@ -1,35 +1,342 @@
 use super::verify_file;
-use crate::{process_csv, CmdExector};
-use clap::Parser;
+use crate::{
+    get_reader, get_writer, process_csv, process_csv_concat, process_csv_dedupe, process_csv_diff,
+    process_csv_join, process_csv_sort, process_csv_stats, process_csv_validate, process_csv_view,
+    process_reverse, Aggregation, CmdExector, CsvDialect, CsvQuery, CsvSchema, CsvTypes, Expr,
+    FlattenOptions, JsonField, SortKey, StatsOptions, TableOptions, ValidationSchema,
+};
+use clap::{ArgAction, Parser};
 use core::fmt;
+use encoding_rs::Encoding;
+use enum_dispatch::enum_dispatch;
 use std::str::FromStr;
 
 #[derive(Debug, Copy, Clone)]
 pub enum OutputFormat {
     Json,
     Yaml,
+    Toml,
+    Ndjson,
+    Xml,
+    Csv,
+    Xlsx,
+    Parquet,
+    Table,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum JoinKind {
+    Inner,
+    Left,
+    Outer,
+}
+
+// 不带子命令时就是格式转换：rcli csv -i input.csv，带子命令时：rcli csv stats -i input.csv
+// 转换的参数和子命令不能同时出现，有子命令时转换参数里必填的 --input 也就不再要求了
+#[derive(Debug, Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+pub struct CsvCommand {
+    #[command(subcommand)]
+    pub cmd: Option<CsvSubCommand>,
+
+    #[command(flatten)]
+    pub convert: Option<CsvOpts>,
+}
+
+#[derive(Debug, Parser)]
+#[enum_dispatch(CmdExector)]
+pub enum CsvSubCommand {
+    #[command(about = "Show per-column statistics or group-by summaries")]
+    Stats(CsvStatsOpts),
+    #[command(about = "Validate every row against a JSON Schema or a column:type spec")]
+    Validate(CsvValidateOpts),
+    #[command(about = "Show rows added, removed and modified between two CSV files")]
+    Diff(CsvDiffOpts),
+    #[command(about = "Join two CSV files on key columns")]
+    Join(CsvJoinOpts),
+    #[command(about = "Concatenate CSV files, the header is the union of all headers")]
+    Concat(CsvConcatOpts),
+    #[command(about = "Sort rows by columns, files larger than memory are sorted on disk")]
+    Sort(CsvSortOpts),
+    #[command(about = "Remove duplicate rows, keeping the first one")]
+    Dedupe(CsvDedupeOpts),
+    #[command(about = "Show CSV as a table in the terminal")]
+    View(CsvViewOpts),
 }
 
 #[derive(Debug, Parser)]
 pub struct CsvOpts {
     // 对 input 做合法性检查，可以写自定义函数或者使用 clap 自带的
-    #[arg(short, long, value_parser = verify_file)]
+    // CsvOpts 里 flatten 了 CsvDialectOpts，clap 就不会自动把字段加到 CsvOpts 这个组里，
+    // 而 CsvCommand 要靠这个组判断有没有传转换参数，所以手动把 input 加进去
+    #[arg(short, long, value_parser = verify_file, group = "CsvOpts")]
     pub input: String,
 
     // default_value 的展开是："output.json".into()，因为 "output.json" 是一个 &str，而 output 要求一个 String
+    // -i - 从 stdin 读，-o - 输出到 stdout；不指定 -o 时输出到 output.{format}
     #[arg(short, long)]
     pub output: Option<String>,
 
-    #[arg(short, long, default_value_t = ',')]
-    pub delimiter: char,
+    #[command(flatten)]
+    pub dialect: CsvDialectOpts,
+
+    // 不指定时，csv 输入默认转成 json，--from 的反向转换默认转成 csv
+    #[arg(long, value_parser = parse_format)]
+    pub format: Option<OutputFormat>,
+
+    // 反向转换：输入是 json/yaml/ndjson/toml，嵌套的字段会被拍平成 a.b 这样的列
+    #[arg(long, value_parser = parse_format)]
+    pub from: Option<OutputFormat>,
+
+    // 反向转换时把这一列的数组展开，每个元素单独一行，比如 --explode tags 或者 --explode club.players，
+    // 使用 --field 时写列名；其他数组还是合并到一个单元格里
+    #[arg(long)]
+    pub explode: Option<String>,
+
+    #[arg(long, default_value = ";")]
+    pub array_sep: String,
+
+    // 反向转换时用 JSONPath 取列，代替整条记录拍平，可以重复：--field name=$.player.name --field tags=$.tags[*]
+    #[arg(long = "field", value_parser = parse_field)]
+    pub fields: Vec<JsonField>,
+
+    // 只输出这些列，按给定的顺序，比如 --select Name,Position
+    #[arg(long, value_delimiter = ',')]
+    pub select: Vec<String>,
+
+    // 重命名列，可以写多次，比如 --rename "Kit Number=kit"
+    #[arg(long, value_parser = parse_rename)]
+    pub rename: Vec<(String, String)>,
+
+    // 只保留满足条件的行，比如 --where 'Position == "Goalkeeper" && `Kit Number` > 10'
+    // where 是关键字，所以字段名用 filter
+    #[arg(long = "where", value_parser = parse_expr)]
+    pub filter: Option<Expr>,
+
+    // 推断单元格的类型：整数、浮点数、布尔，空单元格为 null
+    #[arg(long)]
+    pub infer: bool,
+
+    // 指定列的类型，格式为 yaml/json/toml 的 "列名: 类型"，优先级高于 --infer
+    #[arg(long, value_parser = verify_file)]
+    pub schema: Option<String>,
+
+    // 多线程转换大文件，可以指定线程数，比如 --parallel 8，只写 --parallel 就是每个 CPU 一个线程
+    // 输出的顺序和输入一致；--flexible 时会退回单线程
+    #[arg(long, num_args = 0..=1, default_missing_value = "0")]
+    pub parallel: Option<usize>,
+}
+
+// 读写 csv 时共用的格式参数，转换和各个子命令都会 flatten 进来
+#[derive(Debug, Parser)]
+pub struct CsvDialectOpts {
+    // 分隔符只能是单个 ASCII 字符，tab 可以写成 \t 或者 tab
+    #[arg(short, long, default_value = ",", value_parser = parse_byte)]
+    pub delimiter: u8,
 
     // 注意这种h开头的字母就不能用 short 了，因为每个cli命令都会默认有一个 -h 参数，会重叠
-    #[arg(long, default_value_t = true)]
-    // default_value_t 就是直接传一个字面量，即不会经过 from 转换
+    // SetFalse 表示默认值是 true，传了 --no-header 才变成 false
+    #[arg(long = "no-header", action = ArgAction::SetFalse)]
     pub header: bool,
 
+    #[arg(long, default_value = "\"", value_parser = parse_byte)]
+    pub quote: u8,
+
+    #[arg(long, value_parser = parse_byte)]
+    pub escape: Option<u8>,
+
+    #[arg(long, value_parser = parse_byte)]
+    pub comment: Option<u8>,
+
+    // 允许每一行的列数不一样，转换时要先把所有行读进内存才能确定一共有几列
+    #[arg(long)]
+    pub flexible: bool,
+
+    // 输入的编码，比如 utf-16le、gbk，不指定时按 BOM 自动识别，没有 BOM 就当作 UTF-8
+    #[arg(long, value_parser = parse_encoding)]
+    pub encoding: Option<&'static Encoding>,
+
+    // 输出 csv 时在开头加上 UTF-8 BOM，Windows 上的 Excel 靠它识别编码
+    #[arg(long)]
+    pub bom: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvStatsOpts {
+    #[arg(short, long, value_parser = verify_file)]
+    pub input: String,
+
+    // 默认输出到 stdout
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
     #[arg(long, default_value = "json", value_parser = parse_format)]
     pub format: OutputFormat,
+
+    #[command(flatten)]
+    pub dialect: CsvDialectOpts,
+
+    // 按这些列分组，比如 --group-by Nationality
+    #[arg(long, value_delimiter = ',')]
+    pub group_by: Vec<String>,
+
+    // 分组后的聚合，默认是 count，比如 --agg "count,avg:Kit Number"；不分组时对整个文件聚合
+    #[arg(long, value_delimiter = ',', value_parser = parse_aggregation)]
+    pub agg: Vec<Aggregation>,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvValidateOpts {
+    #[arg(short, long, value_parser = verify_file)]
+    pub input: String,
+
+    // JSON Schema，或者和 --schema 一样的 "列名: 类型" 格式，yaml/json/toml 都可以
+    #[arg(short, long, value_parser = verify_file)]
+    pub schema: String,
+
+    // 默认把错误报告输出到 stdout
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
+    // 遇到第一个错误就停止
+    #[arg(long)]
+    pub strict: bool,
+
+    #[command(flatten)]
+    pub dialect: CsvDialectOpts,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvDiffOpts {
+    // 两个位置参数：rcli csv diff old.csv new.csv --key Name
+    #[arg(value_parser = verify_file)]
+    pub old: String,
+
+    #[arg(value_parser = verify_file)]
+    pub new: String,
+
+    // 用来匹配两个文件里同一行的列，可以是多列，比如 --key Name,DOB
+    #[arg(short, long, required = true, value_delimiter = ',')]
+    pub key: Vec<String>,
+
+    // 不指定时输出方便阅读的文本，也可以是 json 或 yaml
+    #[arg(long, value_parser = parse_format)]
+    pub format: Option<OutputFormat>,
+
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
+    #[command(flatten)]
+    pub dialect: CsvDialectOpts,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvJoinOpts {
+    #[arg(value_parser = verify_file)]
+    pub left: String,
+
+    #[arg(value_parser = verify_file)]
+    pub right: String,
+
+    // 两个文件里都要有这些列，比如 --on Name
+    #[arg(long, required = true, value_delimiter = ',')]
+    pub on: Vec<String>,
+
+    // inner 只保留两边都有的行，left 保留左边所有的行，outer 保留两边所有的行
+    #[arg(long, default_value = "inner", value_parser = parse_join_kind)]
+    pub how: JoinKind,
+
+    #[arg(long, default_value = "csv", value_parser = parse_format)]
+    pub format: OutputFormat,
+
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
+    #[command(flatten)]
+    pub dialect: CsvDialectOpts,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvConcatOpts {
+    // 按顺序拼接，比如 rcli csv concat a.csv b.csv，shell 里也可以直接写 *.csv
+    #[arg(required = true, value_parser = verify_file)]
+    pub inputs: Vec<String>,
+
+    #[arg(long, default_value = "csv", value_parser = parse_format)]
+    pub format: OutputFormat,
+
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
+    #[command(flatten)]
+    pub dialect: CsvDialectOpts,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvSortOpts {
+    #[arg(short, long, value_parser = verify_file)]
+    pub input: String,
+
+    // 列名[:num|str][:asc|desc]，比如 --by "Kit Number:num:desc"，多个键用逗号分隔或者重复 --by
+    #[arg(long, required = true, value_delimiter = ',', value_parser = parse_sort_key)]
+    pub by: Vec<SortKey>,
+
+    #[arg(long, default_value = "csv", value_parser = parse_format)]
+    pub format: OutputFormat,
+
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
+    // 行数据最多占用的内存（MB），输入更大时分段排序后写到临时文件里
+    #[arg(long, default_value_t = 256)]
+    pub buffer_size: usize,
+
+    #[command(flatten)]
+    pub dialect: CsvDialectOpts,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvDedupeOpts {
+    #[arg(short, long, value_parser = verify_file)]
+    pub input: String,
+
+    // 不指定时整行相同才算重复
+    #[arg(long, value_delimiter = ',')]
+    pub by: Vec<String>,
+
+    #[arg(long, default_value = "csv", value_parser = parse_format)]
+    pub format: OutputFormat,
+
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
+    // 行数据最多占用的内存（MB），输入更大时分段排序后写到临时文件里
+    #[arg(long, default_value_t = 256)]
+    pub buffer_size: usize,
+
+    #[command(flatten)]
+    pub dialect: CsvDialectOpts,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvViewOpts {
+    #[arg(short, long, value_parser = verify_file)]
+    pub input: String,
+
+    // 只看前 N 行和（或）后 N 行，中间的行不会留在内存里；只指定 --head 时读够 N 行就停，
+    // 大文件也可以很快看到结果
+    #[arg(long)]
+    pub head: Option<usize>,
+
+    #[arg(long)]
+    pub tail: Option<usize>,
+
+    // 超过这个宽度的单元格会被截断，0 表示不截断
+    #[arg(long, default_value_t = 40)]
+    pub max_width: usize,
+
+    #[command(flatten)]
+    pub dialect: CsvDialectOpts,
 }
 
 // anyhow::Error 可以转为 String 输出到命令行
@@ -46,14 +353,285 @@ fn parse_format(format: &str) -> Result<OutputFormat, anyhow::Error> {
     format.parse()
 }
 
+fn parse_join_kind(s: &str) -> Result<JoinKind, anyhow::Error> {
+    s.parse()
+}
+
+fn parse_sort_key(s: &str) -> Result<SortKey, anyhow::Error> {
+    s.parse()
+}
+
+fn parse_field(s: &str) -> Result<JsonField, anyhow::Error> {
+    s.parse()
+}
+
+fn parse_rename(s: &str) -> Result<(String, String), anyhow::Error> {
+    match s.split_once('=') {
+        Some((from, to)) if !from.is_empty() && !to.is_empty() => {
+            Ok((from.to_string(), to.to_string()))
+        }
+        _ => anyhow::bail!("Expect old=new, got: {}", s),
+    }
+}
+
+fn parse_aggregation(s: &str) -> Result<Aggregation, anyhow::Error> {
+    s.parse()
+}
+
+fn parse_expr(s: &str) -> Result<Expr, anyhow::Error> {
+    s.parse()
+}
+
+// 支持 WHATWG 标准里的所有编码名，比如 gbk、gb18030、utf-16le、windows-1252
+fn parse_encoding(s: &str) -> Result<&'static Encoding, anyhow::Error> {
+    Encoding::for_label(s.trim().as_bytes())
+        .ok_or_else(|| anyhow::anyhow!("Unsupported encoding: {}", s))
+}
+
+fn parse_byte(s: &str) -> Result<u8, anyhow::Error> {
+    match s {
+        "\\t" | "tab" => Ok(b'\t'),
+        s if s.len() == 1 && s.is_ascii() => Ok(s.as_bytes()[0]),
+        _ => anyhow::bail!("Expect a single ASCII character, got: {}", s),
+    }
+}
+
+impl CmdExector for CsvCommand {
+    async fn execute(self) -> anyhow::Result<()> {
+        match (self.cmd, self.convert) {
+            (Some(cmd), _) => cmd.execute().await,
+            (None, Some(opts)) => opts.execute().await,
+            // clap 保证了没有子命令时一定有 --input
+            (None, None) => anyhow::bail!("Either a subcommand or --input is required"),
+        }
+    }
+}
+
 impl CmdExector for CsvOpts {
     async fn execute(self) -> anyhow::Result<()> {
-        let output: String = if let Some(output) = self.output {
-            output
-        } else {
-            format!("output.{}", self.format)
+        let dialect = CsvDialect::from(&self.dialect);
+        let format = self.format.unwrap_or(match self.from {
+            Some(_) => OutputFormat::Csv,
+            None => OutputFormat::Json,
+        });
+        let types = self.types(format)?;
+        let query = CsvQuery {
+            select: self.select,
+            rename: self.rename,
+            filter: self.filter,
+        };
+        // 表格是给人看的，默认直接输出到终端
+        let output: String = match self.output {
+            Some(output) => output,
+            None if matches!(format, OutputFormat::Table) => "-".to_string(),
+            None => format!("output.{}", format),
+        };
+        match self.from {
+            Some(from) => {
+                let flatten = FlattenOptions {
+                    explode: self.explode,
+                    separator: self.array_sep,
+                    fields: self.fields,
+                };
+                process_reverse(
+                    &self.input,
+                    output,
+                    from,
+                    format,
+                    &dialect,
+                    &flatten,
+                    &query,
+                )
+            }
+            // clap 的 requires 在这个 flatten 的组里不起作用，只能在这里检查
+            None if !self.fields.is_empty() => {
+                anyhow::bail!("--field only works with --from json/ndjson/yaml/toml")
+            }
+            None => process_csv(
+                &self.input,
+                output,
+                format,
+                &dialect,
+                &types,
+                &query,
+                self.parallel,
+            ),
+        }
+    }
+}
+
+impl CmdExector for CsvStatsOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let mut reader = get_reader(&self.input)?;
+        let mut writer = get_writer(&self.output)?;
+        let opts = StatsOptions {
+            group_by: self.group_by,
+            aggs: self.agg,
         };
-        process_csv(&self.input, output, self.format)
+        process_csv_stats(
+            &mut reader,
+            &mut writer,
+            self.format,
+            &CsvDialect::from(&self.dialect),
+            &opts,
+        )?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl CmdExector for CsvValidateOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let schema = ValidationSchema::load(&self.schema)?;
+        let mut reader = get_reader(&self.input)?;
+        let mut writer = get_writer(&self.output)?;
+        let report = process_csv_validate(
+            &mut reader,
+            &mut writer,
+            &CsvDialect::from(&self.dialect),
+            &schema,
+            self.strict,
+        )?;
+        writer.flush()?;
+        // 有错误时返回非 0 的退出码，方便在脚本里转换前先检查
+        if !report.is_valid() {
+            anyhow::bail!("{} is not valid", self.input);
+        }
+        Ok(())
+    }
+}
+
+impl CmdExector for CsvDiffOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let mut old = get_reader(&self.old)?;
+        let mut new = get_reader(&self.new)?;
+        let mut writer = get_writer(&self.output)?;
+        process_csv_diff(
+            &mut old,
+            &mut new,
+            &mut writer,
+            self.format,
+            &CsvDialect::from(&self.dialect),
+            &self.key,
+        )?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl CmdExector for CsvJoinOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let mut left = get_reader(&self.left)?;
+        let mut right = get_reader(&self.right)?;
+        let mut writer = get_writer(&self.output)?;
+        process_csv_join(
+            &mut left,
+            &mut right,
+            &mut writer,
+            self.format,
+            &CsvDialect::from(&self.dialect),
+            &self.on,
+            self.how,
+        )?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl CmdExector for CsvConcatOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let mut writer = get_writer(&self.output)?;
+        process_csv_concat(
+            &self.inputs,
+            &mut writer,
+            self.format,
+            &CsvDialect::from(&self.dialect),
+        )?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl CmdExector for CsvSortOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let mut reader = get_reader(&self.input)?;
+        let mut writer = get_writer(&self.output)?;
+        process_csv_sort(
+            &mut reader,
+            &mut writer,
+            self.format,
+            &CsvDialect::from(&self.dialect),
+            &self.by,
+            self.buffer_size.max(1) << 20,
+        )?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl CmdExector for CsvDedupeOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let mut reader = get_reader(&self.input)?;
+        let mut writer = get_writer(&self.output)?;
+        process_csv_dedupe(
+            &mut reader,
+            &mut writer,
+            self.format,
+            &CsvDialect::from(&self.dialect),
+            &self.by,
+            self.buffer_size.max(1) << 20,
+        )?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl CmdExector for CsvViewOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let mut reader = get_reader(&self.input)?;
+        let mut writer = get_writer("-")?;
+        let opts = TableOptions {
+            head: self.head,
+            tail: self.tail,
+            max_width: self.max_width,
+        };
+        process_csv_view(
+            &mut reader,
+            &mut writer,
+            &CsvDialect::from(&self.dialect),
+            &opts,
+        )?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl From<&CsvDialectOpts> for CsvDialect {
+    fn from(opts: &CsvDialectOpts) -> Self {
+        CsvDialect {
+            delimiter: opts.delimiter,
+            has_headers: opts.header,
+            quote: opts.quote,
+            escape: opts.escape,
+            comment: opts.comment,
+            flexible: opts.flexible,
+            encoding: opts.encoding,
+            bom: opts.bom,
+        }
+    }
+}
+
+impl CsvOpts {
+    // xlsx 和 parquet 的列本身带类型，所以不加 --infer 也会推断
+    fn types(&self, format: OutputFormat) -> anyhow::Result<CsvTypes> {
+        let schema = match &self.schema {
+            Some(path) => CsvSchema::load(path)?,
+            None => CsvSchema::default(),
+        };
+        Ok(CsvTypes {
+            infer: self.infer || format.is_binary(),
+            schema,
+        })
     }
 }
 
@@ -62,6 +640,13 @@ impl From<OutputFormat> for &'static str {
         match format {
             OutputFormat::Json => "json",
             OutputFormat::Yaml => "yaml",
+            OutputFormat::Toml => "toml",
+            OutputFormat::Ndjson => "ndjson",
+            OutputFormat::Xml => "xml",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Xlsx => "xlsx",
+            OutputFormat::Parquet => "parquet",
+            OutputFormat::Table => "table",
         }
     }
 }
@@ -73,13 +658,188 @@ impl FromStr for OutputFormat {
         match format.to_lowercase().as_str() {
             "json" => Ok(OutputFormat::Json),
             "yaml" => Ok(OutputFormat::Yaml),
+            "toml" => Ok(OutputFormat::Toml),
+            "ndjson" | "jsonl" => Ok(OutputFormat::Ndjson),
+            "xml" => Ok(OutputFormat::Xml),
+            "csv" => Ok(OutputFormat::Csv),
+            "xlsx" => Ok(OutputFormat::Xlsx),
+            "parquet" => Ok(OutputFormat::Parquet),
+            "table" => Ok(OutputFormat::Table),
             v => anyhow::bail!("Unsupported format: {}", v),
         }
     }
 }
 
+impl OutputFormat {
+    /// xlsx and parquet are binary files that carry column types
+    pub fn is_binary(&self) -> bool {
+        matches!(self, OutputFormat::Xlsx | OutputFormat::Parquet)
+    }
+
+    /// Whether records can be written out one by one, the others are rendered at the end
+    pub fn is_streaming(&self) -> bool {
+        !matches!(
+            self,
+            OutputFormat::Xlsx | OutputFormat::Parquet | OutputFormat::Table
+        )
+    }
+}
+
 impl fmt::Display for OutputFormat {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", Into::<&str>::into(*self))
     }
 }
+
+impl From<JoinKind> for &'static str {
+    fn from(kind: JoinKind) -> Self {
+        match kind {
+            JoinKind::Inner => "inner",
+            JoinKind::Left => "left",
+            JoinKind::Outer => "outer",
+        }
+    }
+}
+
+impl FromStr for JoinKind {
+    type Err = anyhow::Error;
+
+    fn from_str(kind: &str) -> Result<Self, Self::Err> {
+        match kind.to_lowercase().as_str() {
+            "inner" => Ok(JoinKind::Inner),
+            "left" => Ok(JoinKind::Left),
+            "outer" | "full" => Ok(JoinKind::Outer),
+            v => anyhow::bail!("Unsupported join: {}", v),
+        }
+    }
+}
+
+impl fmt::Display for JoinKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_byte() {
+        assert_eq!(parse_byte(";").unwrap(), b';');
+        assert_eq!(parse_byte("\\t").unwrap(), b'\t');
+        assert_eq!(parse_byte("tab").unwrap(), b'\t');
+        assert!(parse_byte("ab").is_err());
+        assert!(parse_byte("é").is_err());
+    }
+
+    #[test]
+    fn test_parse_encoding() {
+        assert_eq!(parse_encoding("gbk").unwrap(), encoding_rs::GBK);
+        assert_eq!(parse_encoding("UTF-16LE").unwrap(), encoding_rs::UTF_16LE);
+        assert!(parse_encoding("klingon").is_err());
+    }
+
+    #[test]
+    fn test_parse_join_kind() {
+        assert_eq!(parse_join_kind("inner").unwrap(), JoinKind::Inner);
+        assert_eq!(parse_join_kind("LEFT").unwrap(), JoinKind::Left);
+        assert_eq!(parse_join_kind("full").unwrap(), JoinKind::Outer);
+        assert!(parse_join_kind("cross").is_err());
+    }
+
+    #[test]
+    fn test_parse_rename() {
+        assert_eq!(
+            parse_rename("Kit Number=kit").unwrap(),
+            ("Kit Number".to_string(), "kit".to_string())
+        );
+        assert!(parse_rename("kit").is_err());
+        assert!(parse_rename("=kit").is_err());
+    }
+
+    #[test]
+    fn test_csv_opts_dialect() {
+        let opts = CsvOpts::parse_from([
+            "csv",
+            "-i",
+            "Cargo.toml",
+            "-d",
+            "tab",
+            "--no-header",
+            "--comment",
+            "#",
+        ]);
+        let dialect = CsvDialect::from(&opts.dialect);
+        assert_eq!(dialect.delimiter, b'\t');
+        assert!(!dialect.has_headers);
+        assert_eq!(dialect.quote, b'"');
+        assert_eq!(dialect.comment, Some(b'#'));
+        assert!(!dialect.flexible);
+    }
+
+    #[test]
+    fn test_csv_command_parse() {
+        let cmd = CsvCommand::parse_from(["csv", "-i", "Cargo.toml"]);
+        assert!(cmd.cmd.is_none());
+        let opts = cmd.convert.unwrap();
+        assert_eq!(opts.input, "Cargo.toml");
+        assert_eq!(opts.parallel, None);
+
+        let cmd = CsvCommand::parse_from(["csv", "-i", "Cargo.toml", "--parallel"]);
+        assert_eq!(cmd.convert.unwrap().parallel, Some(0));
+        let cmd = CsvCommand::parse_from(["csv", "-i", "Cargo.toml", "--parallel", "4"]);
+        assert_eq!(cmd.convert.unwrap().parallel, Some(4));
+
+        let cmd = CsvCommand::parse_from([
+            "csv",
+            "-i",
+            "Cargo.toml",
+            "--from",
+            "jsonl",
+            "--field",
+            "name=$.player.name",
+        ]);
+        let opts = cmd.convert.unwrap();
+        assert_eq!(opts.fields.len(), 1);
+        assert_eq!(opts.fields[0].to_string(), "name=$.player.name");
+        assert!(
+            CsvCommand::try_parse_from(["csv", "-i", "Cargo.toml", "--field", "name"]).is_err()
+        );
+
+        let cmd = CsvCommand::parse_from([
+            "csv",
+            "stats",
+            "-i",
+            "Cargo.toml",
+            "--group-by",
+            "Nationality",
+            "--agg",
+            "count,avg:Kit Number",
+        ]);
+        assert!(cmd.convert.is_none());
+        let Some(CsvSubCommand::Stats(opts)) = cmd.cmd else {
+            panic!("expect stats subcommand");
+        };
+        assert_eq!(opts.group_by, vec!["Nationality"]);
+        assert_eq!(opts.agg.len(), 2);
+
+        let cmd = CsvCommand::parse_from([
+            "csv",
+            "sort",
+            "-i",
+            "Cargo.toml",
+            "--by",
+            "Kit Number:num:desc,Name",
+        ]);
+        let Some(CsvSubCommand::Sort(opts)) = cmd.cmd else {
+            panic!("expect sort subcommand");
+        };
+        assert_eq!(opts.by.len(), 2);
+        assert!(opts.by[0].numeric && opts.by[0].descending);
+        assert_eq!(opts.buffer_size, 256);
+
+        assert!(CsvCommand::try_parse_from(["csv"]).is_err());
+        assert!(CsvCommand::try_parse_from(["csv", "-i", "Cargo.toml", "stats"]).is_err());
+    }
+}
@@ -1,38 +1,488 @@
-use crate::{process_genpass, CmdExector};
-use clap::Parser;
+use super::{verify_file, OutputFormat, SeedOpts};
+use crate::{
+    get_reader, get_writer, password_report, process_genpass_audit, process_genpass_with_rng,
+    process_passphrase_with_rng, process_password_reports, CmdExector, GenPassOptions, Passphrase,
+    PassphraseOptions, PasswordPolicy, Wordlist, MAX_PASSWORD_LENGTH, MIN_PASSWORD_LENGTH,
+};
+use clap::{ArgAction, Parser};
+use enum_dispatch::enum_dispatch;
+use rand::rngs::StdRng;
+use std::{
+    io::{self, Write},
+    path::PathBuf,
+};
 use zxcvbn::zxcvbn;
 
+// 和 csv 一样，不带子命令时生成密码：rcli genpass -l 20，带子命令时：rcli genpass check --policy policy.toml
+#[derive(Debug, Parser)]
+#[command(args_conflicts_with_subcommands = true)]
+pub struct GenPassCommand {
+    #[command(subcommand)]
+    pub cmd: Option<GenPassSubCommand>,
+
+    #[command(flatten)]
+    pub generate: GenPassOpts,
+}
+
+#[derive(Debug, Parser)]
+#[enum_dispatch(CmdExector)]
+pub enum GenPassSubCommand {
+    #[command(about = "Check a password against a policy file and show the rules it breaks")]
+    Check(GenPassCheckOpts),
+    #[command(about = "Show zxcvbn's strength report for passwords read one per line")]
+    Audit(GenPassAuditOpts),
+}
+
+#[derive(Debug, Parser)]
+pub struct GenPassCheckOpts {
+    #[arg(long, value_parser = verify_file)]
+    pub policy: String,
+
+    // 不在命令行上给出密码时从 stdin 读一行，避免密码留在 shell 历史里
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct GenPassAuditOpts {
+    // 默认从 stdin 读，每行一个密码
+    #[arg(short, long, value_parser = verify_file, default_value = "-")]
+    pub input: String,
+
+    // patterns 和 suggestions 是列表，yaml 看起来最清楚
+    #[arg(long, value_parser = parse_format, default_value = "yaml")]
+    pub format: OutputFormat,
+
+    // 攻击者会先试的词，比如公司名、用户名，可以传多次
+    #[arg(short, long = "user-input")]
+    pub user_inputs: Vec<String>,
+}
+
 #[derive(Debug, Parser)]
 pub struct GenPassOpts {
-    #[arg(short, long, default_value_t = 16)]
+    #[arg(short, long, default_value_t = 16, value_parser = parse_length)]
     pub length: u8,
 
-    #[arg(long, default_value_t = true)]
+    // bool 加 default_value_t = true 的话命令行上没法关掉，
+    // 所以和 csv 的 --no-header 一样用 SetFalse：默认为 true，传了 --no-xxx 才变成 false
+    #[arg(long = "no-uppercase", action = ArgAction::SetFalse)]
     pub uppercase: bool,
 
-    #[arg(long, default_value_t = true)]
+    #[arg(long = "no-lowercase", action = ArgAction::SetFalse)]
     pub lowercase: bool,
 
-    #[arg(long, default_value_t = true)]
+    #[arg(long = "no-number", action = ArgAction::SetFalse)]
     pub number: bool,
 
-    #[arg(long, default_value_t = true)]
+    #[arg(long = "no-symbol", action = ArgAction::SetFalse)]
     pub symbol: bool,
+
+    // 只从这些字符里选，比如 --charset 0123456789abcdef，这时上面几种字符的开关都不起作用
+    #[arg(long, conflicts_with_all = ["uppercase", "lowercase", "number", "symbol", "symbols", "ambiguous"])]
+    pub charset: Option<String>,
+
+    // 有的系统不接受某些字符，比如 --exclude '^&'
+    #[arg(long, default_value = "")]
+    pub exclude: String,
+
+    // 替换默认的特殊字符 !@#$%^&*_
+    #[arg(long = "include-symbols", conflicts_with = "symbol")]
+    pub symbols: Option<String>,
+
+    // 默认会去掉容易看错的 I、l、O、0，加上这个参数保留它们
+    #[arg(long)]
+    pub ambiguous: bool,
+
+    // 生成几个单词组成的密码短语，比随机字符好记也好输入
+    #[arg(long, conflicts_with_all = ["length", "uppercase", "lowercase", "number", "symbol", "charset", "exclude", "symbols", "ambiguous"])]
+    pub passphrase: bool,
+
+    #[arg(long, default_value_t = 6, value_parser = parse_words, requires = "passphrase")]
+    pub words: u8,
+
+    #[arg(long, default_value = "-", requires = "passphrase")]
+    pub separator: String,
+
+    // 每个单词首字母大写
+    #[arg(long, requires = "passphrase")]
+    pub capitalize: bool,
+
+    // 在随机一个单词后面加一个数字
+    #[arg(long, requires = "passphrase")]
+    pub add_digit: bool,
+
+    // 在随机一个单词后面加一个特殊字符
+    #[arg(long, requires = "passphrase")]
+    pub add_symbol: bool,
+
+    // 默认用内置的 BIP39 英文单词表，也可以指定 EFF 等 diceware 单词表
+    #[arg(long, requires = "passphrase")]
+    pub wordlist: Option<PathBuf>,
+
+    // 按 TOML/YAML 策略文件里的规则生成，不满足就重新生成
+    #[arg(long, value_parser = verify_file, conflicts_with = "passphrase")]
+    pub policy: Option<String>,
+
+    // 一次生成多个密码
+    #[arg(short, long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    pub count: u16,
+
+    // 指定格式时，每个密码连同强度、熵和破解时间一起输出，方便脚本处理
+    #[arg(long, value_parser = parse_format)]
+    pub format: Option<OutputFormat>,
+
+    #[command(flatten)]
+    pub seed: SeedOpts,
+}
+
+// 在解析参数时就检查长度，给出和 process_genpass 一样的提示
+fn parse_length(s: &str) -> Result<u8, anyhow::Error> {
+    match s.parse::<u8>() {
+        Ok(length) if (MIN_PASSWORD_LENGTH..=MAX_PASSWORD_LENGTH).contains(&length) => Ok(length),
+        _ => anyhow::bail!(
+            "Password length must be between {} and {}, got {}",
+            MIN_PASSWORD_LENGTH,
+            MAX_PASSWORD_LENGTH,
+            s
+        ),
+    }
+}
+
+// 单词太少的话熵太低，太多又不好记
+fn parse_words(s: &str) -> Result<u8, anyhow::Error> {
+    match s.parse::<u8>() {
+        Ok(words) if (1..=32).contains(&words) => Ok(words),
+        _ => anyhow::bail!("Passphrase must have between 1 and 32 words, got {}", s),
+    }
+}
+
+// xlsx 和 parquet 是二进制文件，不适合输出到终端
+fn parse_format(s: &str) -> Result<OutputFormat, anyhow::Error> {
+    let format: OutputFormat = s.parse()?;
+    if format.is_binary() {
+        anyhow::bail!("genpass can't output {} to stdout", format);
+    }
+    Ok(format)
+}
+
+impl GenPassOpts {
+    // 策略文件只在这里读一次，批量生成时共用
+    fn options(&self) -> anyhow::Result<GenPassOptions> {
+        let mut opts = GenPassOptions::from(self);
+        if let Some(policy) = &self.policy {
+            opts.policy = Some(PasswordPolicy::load(policy)?);
+        }
+        Ok(opts)
+    }
+
+    #[cfg(test)]
+    fn generate(&self) -> anyhow::Result<String> {
+        process_genpass_with_rng(&self.options()?, &mut self.seed.rng())
+    }
+
+    // 按 --passphrase 选择生成方式，同时返回熵
+    fn generate_with_entropy(
+        &self,
+        opts: &GenPassOptions,
+        rng: &mut StdRng,
+    ) -> anyhow::Result<(String, f64)> {
+        if self.passphrase {
+            let ret = self.generate_passphrase(rng)?;
+            return Ok((ret.phrase, ret.entropy));
+        }
+        Ok((process_genpass_with_rng(opts, rng)?, opts.entropy()?))
+    }
+
+    fn generate_passphrase(&self, rng: &mut StdRng) -> anyhow::Result<Passphrase> {
+        let wordlist = match &self.wordlist {
+            Some(path) => Wordlist::load(path)?,
+            None => Wordlist::builtin(),
+        };
+        process_passphrase_with_rng(&PassphraseOptions::from(self), &wordlist, rng)
+    }
+}
+
+impl From<&GenPassOpts> for PassphraseOptions {
+    fn from(opts: &GenPassOpts) -> Self {
+        PassphraseOptions {
+            words: opts.words,
+            separator: opts.separator.clone(),
+            capitalize: opts.capitalize,
+            digit: opts.add_digit,
+            symbol: opts.add_symbol,
+        }
+    }
+}
+
+impl From<&GenPassOpts> for GenPassOptions {
+    fn from(opts: &GenPassOpts) -> Self {
+        GenPassOptions {
+            length: opts.length,
+            upper: opts.uppercase,
+            lower: opts.lowercase,
+            number: opts.number,
+            symbol: opts.symbol,
+            charset: opts.charset.clone(),
+            exclude: opts.exclude.clone(),
+            symbols: opts.symbols.clone(),
+            ambiguous: opts.ambiguous,
+            policy: None,
+        }
+    }
+}
+
+impl CmdExector for GenPassCommand {
+    async fn execute(self) -> anyhow::Result<()> {
+        match self.cmd {
+            Some(cmd) => cmd.execute().await,
+            None => self.generate.execute().await,
+        }
+    }
+}
+
+impl CmdExector for GenPassCheckOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let policy = PasswordPolicy::load(&self.policy)?;
+        let password = match self.password {
+            Some(password) => password,
+            None => {
+                let mut line = String::new();
+                io::stdin().read_line(&mut line)?;
+                line.trim_end_matches(['\r', '\n']).to_string()
+            }
+        };
+
+        let violations = policy.check(&password);
+        if violations.is_empty() {
+            println!("Password follows the policy");
+            return Ok(());
+        }
+        for violation in &violations {
+            println!("{}", violation);
+        }
+        // 返回错误让退出码不为 0，方便脚本判断
+        anyhow::bail!("Password breaks {} policy rule(s)", violations.len())
+    }
+}
+
+impl CmdExector for GenPassAuditOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let mut reader = get_reader(&self.input)?;
+        let mut writer = get_writer("-")?;
+        process_genpass_audit(&mut reader, &mut writer, self.format, &self.user_inputs)?;
+        writer.flush()?;
+        Ok(())
+    }
 }
 
 impl CmdExector for GenPassOpts {
     async fn execute(self) -> anyhow::Result<()> {
-        let ret = process_genpass(
-            self.length,
-            self.uppercase,
-            self.lowercase,
-            self.number,
-            self.number,
-        )?;
-        println!("{}", ret);
-
-        let estimate = zxcvbn(&ret, &[])?;
-        eprintln!("Password strength: {}", estimate.score());
+        let opts = self.options()?;
+        // 批量生成时共用一个随机数生成器，不管有没有 --seed，每个密码都不一样；指定 --seed 时整批输出可以复现
+        let mut rng = self.seed.rng();
+        let passwords = (0..self.count)
+            .map(|_| self.generate_with_entropy(&opts, &mut rng))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        if let Some(format) = self.format {
+            let reports = passwords
+                .into_iter()
+                .map(|(password, entropy)| password_report(password, entropy))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            return process_password_reports(&mut io::stdout(), format, &reports);
+        }
+
+        for (password, entropy) in passwords {
+            println!("{}", password);
+
+            let estimate = zxcvbn(&password, &[])?;
+            // 用 eprintln 是为了输出到 std error，如果程序需要输出密码到文件，如 cargo run -- genpass > out.txt
+            // 不会与 std out 的数据混合，即运行 cargo run -- genpass > out.txt，只会输出 Password strength
+            // 如果用 println 的话，在输出到文件时，会把 println 的内容也输出到文件
+            eprintln!("Password strength: {}", estimate.score()); // 16位的长度是4，4表示足够强了
+            if self.passphrase {
+                eprintln!("Entropy: {:.1} bits", entropy);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_length() {
+        assert_eq!(parse_length("16").unwrap(), 16);
+        assert_eq!(parse_length("4").unwrap(), MIN_PASSWORD_LENGTH);
+        assert_eq!(parse_length("128").unwrap(), MAX_PASSWORD_LENGTH);
+        assert!(parse_length("3").is_err());
+        assert!(parse_length("129").is_err());
+        assert!(parse_length("-1").is_err());
+        assert!(parse_length("abc").is_err());
+        assert!(GenPassOpts::try_parse_from(["genpass", "-l", "2"]).is_err());
+    }
+
+    #[test]
+    fn test_genpass_class_flags() -> anyhow::Result<()> {
+        let opts = GenPassOpts::parse_from(["genpass"]);
+        assert!(opts.uppercase && opts.lowercase && opts.number && opts.symbol);
+
+        // 只留下一种字符，生成的密码里只能有这一种
+        for flag in [
+            "--no-uppercase",
+            "--no-lowercase",
+            "--no-number",
+            "--no-symbol",
+        ] {
+            let opts = GenPassOpts::parse_from(["genpass", "-l", "64", flag]);
+            let password = opts.generate()?;
+            let allowed = password.bytes().all(|c| match flag {
+                "--no-uppercase" => !c.is_ascii_uppercase(),
+                "--no-lowercase" => !c.is_ascii_lowercase(),
+                "--no-number" => !c.is_ascii_digit(),
+                _ => c.is_ascii_alphanumeric(),
+            });
+            assert!(allowed, "{}: {}", flag, password);
+        }
+
+        let opts = GenPassOpts::parse_from([
+            "genpass",
+            "--no-uppercase",
+            "--no-lowercase",
+            "--no-number",
+            "--no-symbol",
+        ]);
+        assert!(opts.generate().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_genpass_charset_opts() -> anyhow::Result<()> {
+        let opts = GenPassOpts::parse_from(["genpass", "--charset", "0123456789abcdef"]);
+        let password = opts.generate()?;
+        assert!(password.chars().all(|c| c.is_ascii_hexdigit()));
+
+        let opts = GenPassOpts::parse_from(["genpass", "--include-symbols", "-", "--exclude", "^"]);
+        assert_eq!(opts.symbols.as_deref(), Some("-"));
+        assert!(opts.generate()?.contains('-'));
+
+        assert!(
+            GenPassOpts::try_parse_from(["genpass", "--charset", "ab", "--no-number"]).is_err()
+        );
+        assert!(
+            GenPassOpts::try_parse_from(["genpass", "--no-symbol", "--include-symbols", "-"])
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_genpass_passphrase_opts() -> anyhow::Result<()> {
+        let opts = GenPassOpts::parse_from([
+            "genpass",
+            "--passphrase",
+            "--words",
+            "4",
+            "--separator",
+            ".",
+            "--capitalize",
+            "--add-digit",
+        ]);
+        let ret = opts.generate_passphrase(&mut opts.seed.rng())?;
+        let words: Vec<&str> = ret.phrase.split('.').collect();
+        assert_eq!(words.len(), 4);
+        assert!(words
+            .iter()
+            .all(|w| w.starts_with(|c: char| c.is_ascii_uppercase())));
+        assert_eq!(ret.phrase.chars().filter(|c| c.is_ascii_digit()).count(), 1);
+        assert!((ret.entropy - (44.0 + 10f64.log2() + 2.0)).abs() < 1e-9);
+
+        // 短语相关的参数只能和 --passphrase 一起用
+        assert!(GenPassOpts::try_parse_from(["genpass", "--words", "4"]).is_err());
+        assert!(GenPassOpts::try_parse_from(["genpass", "--passphrase", "-l", "20"]).is_err());
+        assert!(GenPassOpts::try_parse_from(["genpass", "--passphrase", "--words", "0"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_genpass_count_and_format() -> anyhow::Result<()> {
+        let opts = GenPassOpts::parse_from(["genpass", "-c", "3", "--format", "yaml"]);
+        assert_eq!(opts.count, 3);
+        assert!(matches!(opts.format, Some(OutputFormat::Yaml)));
+        let (password, entropy) =
+            opts.generate_with_entropy(&opts.options()?, &mut opts.seed.rng())?;
+        assert_eq!(password.len(), 16);
+        assert!(entropy > 90.0);
+
+        let opts = GenPassOpts::parse_from(["genpass", "--passphrase", "--words", "5"]);
+        let (_, entropy) = opts.generate_with_entropy(&opts.options()?, &mut opts.seed.rng())?;
+        assert_eq!(entropy, 55.0);
+
+        assert!(GenPassOpts::try_parse_from(["genpass", "-c", "0"]).is_err());
+        assert!(GenPassOpts::try_parse_from(["genpass", "--format", "xlsx"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_genpass_policy_opts() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("policy.toml");
+        std::fs::write(&path, "min_number = 4\nmax_repeat = 1\n")?;
+        let path = path.to_str().unwrap();
+
+        let opts = GenPassOpts::parse_from(["genpass", "--policy", path]);
+        let password = opts.generate()?;
+        assert!(password.chars().filter(|c| c.is_ascii_digit()).count() >= 4);
+
+        let cmd = GenPassCommand::parse_from(["genpass", "check", "--policy", path, "a1"]);
+        match cmd.cmd {
+            Some(GenPassSubCommand::Check(opts)) => {
+                assert_eq!(opts.password.as_deref(), Some("a1"));
+            }
+            _ => panic!("expected the check subcommand"),
+        }
+        let cmd = GenPassCommand::parse_from(["genpass", "-l", "20"]);
+        assert!(cmd.cmd.is_none());
+        assert_eq!(cmd.generate.length, 20);
+
+        assert!(GenPassCommand::try_parse_from(["genpass", "-l", "20", "check"]).is_err());
+        assert!(
+            GenPassOpts::try_parse_from(["genpass", "--passphrase", "--policy", path]).is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_genpass_audit_opts() {
+        let cmd = GenPassCommand::parse_from(["genpass", "audit", "-u", "acme", "-u", "alice"]);
+        match cmd.cmd {
+            Some(GenPassSubCommand::Audit(opts)) => {
+                assert_eq!(opts.input, "-");
+                assert!(matches!(opts.format, OutputFormat::Yaml));
+                assert_eq!(opts.user_inputs, ["acme", "alice"]);
+            }
+            _ => panic!("expected the audit subcommand"),
+        }
+    }
+
+    #[test]
+    fn test_genpass_seed() -> anyhow::Result<()> {
+        let generate = |args: &[&str]| -> anyhow::Result<Vec<String>> {
+            let opts = GenPassOpts::parse_from(args);
+            let mut rng = opts.seed.rng();
+            (0..3)
+                .map(|_| Ok(opts.generate_with_entropy(&opts.options()?, &mut rng)?.0))
+                .collect()
+        };
+        let a = generate(&["genpass", "--seed", "42"])?;
+        assert_eq!(a, generate(&["genpass", "--seed", "42"])?);
+        assert_ne!(a, generate(&["genpass", "--seed", "43"])?);
+        // 同一个种子下批量生成的密码互不相同
+        assert_ne!(a[0], a[1]);
+
+        let args = ["genpass", "--passphrase", "--seed", "42"];
+        assert_eq!(generate(&args)?, generate(&args)?);
         Ok(())
     }
 }
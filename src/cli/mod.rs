@@ -4,8 +4,9 @@ mod genpass_opts;
 mod http;
 mod text;
 
-use clap::Parser;
+use clap::{Args, Parser};
 use enum_dispatch::enum_dispatch;
+use rand::{rngs::StdRng, SeedableRng};
 use std::path::{Path, PathBuf};
 
 // 这里用 self::csv 的原因是，如果不用 self 的话，会与 Cargo.toml 里的 csv crate 冲突
@@ -20,13 +21,15 @@ pub struct Opts {
 
 #[derive(Debug, Parser)]
 #[enum_dispatch(CmdExector)]
+// 命令行参数只在启动时解析一次，变体大小不一样没关系
+#[allow(clippy::large_enum_variant)]
 pub enum Subcommand {
     // name 可以不指定，默认就是转成小写
-    #[command(name = "csv", about = "Convert CSV to other format")]
-    Csv(CsvOpts),
+    #[command(name = "csv", about = "Convert CSV to other format, or inspect it")]
+    Csv(CsvCommand),
 
     #[command(name = "genpass", about = "generate a random password")]
-    GenPass(GenPassOpts),
+    GenPass(GenPassCommand),
 
     #[command(subcommand, about = "Base encode/decode")]
     Base64(Base64SubCommand),
@@ -71,6 +74,25 @@ fn verify_path(path: &str) -> Result<PathBuf, &'static str> {
     }
 }
 
+// 生成密码和密钥的命令共用，固定随机数种子后输出可以逐字节比较，方便写测试
+// --seed 只在单元测试里有，而且不出现在帮助里，编译出来的程序总是用系统的随机数
+#[derive(Debug, Args)]
+pub struct SeedOpts {
+    #[cfg(test)]
+    #[arg(long, hide = true)]
+    pub seed: Option<u64>,
+}
+
+impl SeedOpts {
+    pub fn rng(&self) -> StdRng {
+        #[cfg(test)]
+        if let Some(seed) = self.seed {
+            return StdRng::seed_from_u64(seed);
+        }
+        StdRng::from_entropy()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
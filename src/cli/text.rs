@@ -1,7 +1,7 @@
-use super::{verify_file, verify_path};
+use super::{verify_file, verify_path, SeedOpts};
 use crate::{
-    get_content, get_reader, process_text_generate, process_text_sign, process_text_verify,
-    CmdExector,
+    get_content, get_reader, process_text_generate_with_rng, process_text_sign,
+    process_text_verify, CmdExector,
 };
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
 use clap::Parser;
@@ -58,6 +58,9 @@ pub struct KeyGenerateOpts {
 
     #[arg(short, long, value_parser = verify_path)]
     pub output: PathBuf,
+
+    #[command(flatten)]
+    pub seed: SeedOpts,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -126,7 +129,7 @@ impl CmdExector for TextVerifyOpts {
 
 impl CmdExector for KeyGenerateOpts {
     async fn execute(self) -> anyhow::Result<()> {
-        let key = process_text_generate(self.format)?;
+        let key = process_text_generate_with_rng(self.format, &mut self.seed.rng())?;
         for (k, v) in key {
             fs::write(self.output.join(k), v).await?;
         }
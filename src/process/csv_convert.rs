@@ -1,53 +1,1033 @@
-use crate::cli::OutputFormat;
-use anyhow::Result;
-use csv::Reader;
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use std::fs;
-// juventus.csv 的字段结构
-#[derive(Debug, Deserialize, Serialize)]
-struct Player {
-    #[serde(rename = "Name")]
-    name: String,
+use super::{
+    csv_parquet::ParquetWriter,
+    csv_table::{TableOptions, TableWriter},
+    csv_xlsx::XlsxWriter,
+    process_csv_parallel, BoundQuery, CsvQuery, CsvTypes,
+};
+use crate::{cli::OutputFormat, get_reader, get_writer};
+use anyhow::{Context, Result};
+use csv::{ReaderBuilder, StringRecord, WriterBuilder};
+use encoding_rs::Encoding;
+use encoding_rs_io::{DecodeReaderBytes, DecodeReaderBytesBuilder};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::io::{Read, Write};
 
-    #[serde(rename = "Position")]
-    position: String,
+/// How the input CSV is laid out: delimiter, quoting and header handling
+#[derive(Debug, Clone, Copy)]
+pub struct CsvDialect {
+    pub delimiter: u8,
+    pub has_headers: bool,
+    pub quote: u8,
+    pub escape: Option<u8>,
+    pub comment: Option<u8>,
+    pub flexible: bool,
+    /// Input encoding, `None` means UTF-8 unless the input starts with a BOM
+    pub encoding: Option<&'static Encoding>,
+    /// Start CSV output with a UTF-8 BOM, Excel needs it to detect the encoding
+    pub bom: bool,
+}
+
+// 逐条写出转换后的记录，写出器内部只保存当前这一条，这样内存占用跟文件大小无关
+pub trait RecordWriter {
+    /// Receive the column order before the first record, only CSV output cares about it
+    fn write_headers(&mut self, _headers: &[String]) -> Result<()> {
+        Ok(())
+    }
+    /// Write a single converted record
+    fn write(&mut self, record: &Value) -> Result<()>;
+    /// Write whatever is needed to close the document
+    fn finish(&mut self) -> Result<()>;
+    /// Act as if records were already written, so no document header is emitted
+    fn resume(&mut self) {}
+    /// No more records are needed, e.g. `csv view --head` without `--tail`, so reading can stop
+    fn is_full(&self) -> bool {
+        false
+    }
+    /// Append records encoded by a resumed writer of the same format, e.g. on another thread
+    fn write_encoded(&mut self, encoded: &[u8], records: usize) -> Result<()>;
+}
+
+struct JsonWriter<'a> {
+    writer: &'a mut dyn Write,
+    count: usize,
+}
 
-    #[serde(rename = "DOB")]
-    dob: String, // Date of Birth
+struct YamlWriter<'a> {
+    writer: &'a mut dyn Write,
+    count: usize,
+}
 
-    #[serde(rename = "Nationality")]
-    nationality: String,
+struct TomlWriter<'a> {
+    writer: &'a mut dyn Write,
+    count: usize,
+}
 
-    #[serde(rename = "Kit Number")]
-    kit: u8, // 球衣号
+struct NdjsonWriter<'a> {
+    writer: &'a mut dyn Write,
 }
 
-pub fn process_csv(input: &str, output: String, format: OutputFormat) -> Result<()> {
-    let mut reader: Reader<fs::File> = Reader::from_path(input)?;
+struct XmlWriter<'a> {
+    writer: &'a mut dyn Write,
+    count: usize,
+}
 
-    let mut ret = Vec::with_capacity(128);
+struct CsvWriter<'a> {
+    // 只有在 write_encoded 里会短暂地取出来
+    writer: Option<csv::Writer<&'a mut dyn Write>>,
+    builder: WriterBuilder,
+    has_headers: bool,
+    headers: Vec<String>,
+}
 
-    let headers: csv::StringRecord = reader.headers()?.clone();
+// TOML 的顶层必须是一个表，所以每一行都作为 [[row]] 数组表里的一项
+#[derive(Serialize)]
+struct TomlRows<'a> {
+    row: [&'a Value; 1],
+}
 
-    for result in reader.records() {
-        let record: csv::StringRecord = result?;
-        // headers.iter() -> 使用 headers 的迭代器
-        // record.iter() -> 使用 record 的迭代器
-        // zip() -> 将两个迭代器合并为一个元组的迭代器 [(header, record), ..]
-        // collect::<Value>() -> 将元组的迭代器转换为 JSON Value
-        // 生成的是 Json Value，但是因为 Json Value 实现了 Deserialize, Serialize，所以最后是 Value
-        let json_value = headers.iter().zip(record.iter()).collect::<Value>();
-        // println!("{:?}", record);
-        ret.push(json_value);
+pub fn process_csv(
+    input: &str,
+    output: String,
+    format: OutputFormat,
+    dialect: &CsvDialect,
+    types: &CsvTypes,
+    query: &CsvQuery,
+    jobs: Option<usize>,
+) -> Result<()> {
+    // csv::Reader 内部自带缓冲，所以这里不需要再包一层 BufReader
+    // "-" 表示从 stdin 读、写到 stdout，这样可以放在管道里使用
+    let mut reader = get_reader(input)?;
+    let mut writer = get_writer(&output)?;
+
+    // flexible 模式下后面的行可能会增加列，只能顺序处理；xlsx、parquet 和表格要在最后整体写出，也没法并行
+    match jobs {
+        Some(jobs) if !dialect.flexible && format.is_streaming() => process_csv_parallel(
+            &mut reader,
+            &mut writer,
+            format,
+            dialect,
+            types,
+            query,
+            jobs,
+        )?,
+        _ => process_csv_stream(&mut reader, &mut writer, format, dialect, types, query)?,
     }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Convert CSV from the reader and stream the result to the writer, one record at a time
+pub fn process_csv_stream(
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    format: OutputFormat,
+    dialect: &CsvDialect,
+    types: &CsvTypes,
+    query: &CsvQuery,
+) -> Result<()> {
+    let mut writer = record_writer(writer, format, dialect)?;
+    write_records(reader, writer.as_mut(), dialect, types, query)
+}
 
-    let content = match format {
-        OutputFormat::Json => serde_json::to_string_pretty(&ret)?,
-        OutputFormat::Yaml => serde_yaml::to_string(&ret)?,
+// 读出表头和每一行，按查询条件筛选、转换后交给 writer，最后结束文档
+pub(super) fn write_records(
+    reader: &mut dyn Read,
+    writer: &mut dyn RecordWriter,
+    dialect: &CsvDialect,
+    types: &CsvTypes,
+    query: &CsvQuery,
+) -> Result<()> {
+    let mut reader = dialect.reader_builder().from_reader(dialect.decode(reader));
+    // 没有表头时，列名按位置生成 col0..colN
+    let mut headers: StringRecord = if dialect.has_headers {
+        reader.headers()?.clone()
+    } else {
+        StringRecord::new()
     };
 
-    fs::write(output, content)?;
+    // flexible 模式下后面的行可能比表头长，csv、表格这些格式的表头一旦写出就改不了了，
+    // 所以先把所有行读进内存，按最长的一行补齐列名，多出来的单元格不会丢
+    let mut rows = Vec::new();
+    if dialect.flexible {
+        for record in reader.records() {
+            rows.push(record?);
+        }
+        let width = rows.iter().map(|r| r.len()).max().unwrap_or_default();
+        extend_headers(&mut headers, width);
+    }
 
-    Ok(())
+    // 有表头时直接就能确定输出哪些列；没有表头的话要等读到第一行才知道有几列
+    let mut bound = None;
+    if dialect.has_headers || !headers.is_empty() {
+        let q = query.bind(&headers)?;
+        writer.write_headers(&q.headers())?;
+        bound = Some(q);
+    }
+
+    for record in &rows {
+        write_record(record, writer, &mut headers, &mut bound, types, query)?;
+    }
+    // read_record 会复用同一个 record 的内存，不会每行都重新分配
+    let mut record = StringRecord::new();
+    while !writer.is_full() && reader.read_record(&mut record)? {
+        write_record(&record, writer, &mut headers, &mut bound, types, query)?;
+    }
+
+    writer.finish()
+}
+
+fn write_record(
+    record: &StringRecord,
+    writer: &mut dyn RecordWriter,
+    headers: &mut StringRecord,
+    bound: &mut Option<BoundQuery>,
+    types: &CsvTypes,
+    query: &CsvQuery,
+) -> Result<()> {
+    // 不是 flexible 模式时每一行的列数都一样，第一行就能确定表头
+    if bound.is_none() {
+        extend_headers(headers, record.len());
+        let q = query.bind(headers)?;
+        writer.write_headers(&q.headers())?;
+        *bound = Some(q);
+    }
+    let query = bound.as_ref().expect("query is bound above");
+    if !query.matches(record) {
+        return Ok(());
+    }
+    writer.write(&record_to_value(record, query, types)?)
+}
+
+// 按位置给表头之外的列命名 colN，和已有的列名重复时加上后缀，比如 col5_1
+fn extend_headers(headers: &mut StringRecord, width: usize) {
+    for i in headers.len()..width {
+        let mut name = format!("col{}", i);
+        let mut n = 0;
+        while headers.iter().any(|h| h == name) {
+            n += 1;
+            name = format!("col{}_{}", i, n);
+        }
+        headers.push_field(&name);
+    }
+}
+
+// 按 --select 的顺序取列，每个单元格按 types 的规则转换成 JSON Value（默认都是字符串）
+// 类型是按原来的列名指定的，输出的时候再用重命名后的列名
+pub(super) fn record_to_value(
+    record: &StringRecord,
+    query: &BoundQuery,
+    types: &CsvTypes,
+) -> Result<Value> {
+    let mut row = Map::with_capacity(record.len());
+    for (i, name, output) in query.columns() {
+        // flexible 模式下这一行可能比表头短
+        let Some(cell) = record.get(i) else {
+            continue;
+        };
+        let value = types.parse(name, cell).with_context(|| {
+            let line = record.position().map(|p| p.line()).unwrap_or_default();
+            format!("Invalid value at line {}", line)
+        })?;
+        row.insert(output.to_string(), value);
+    }
+    Ok(Value::Object(row))
+}
+
+/// Pick the writer for the output format, CSV output follows the same dialect as the input
+pub fn record_writer<'a>(
+    writer: &'a mut dyn Write,
+    format: OutputFormat,
+    dialect: &CsvDialect,
+) -> Result<Box<dyn RecordWriter + 'a>> {
+    // BOM 只对 csv 有意义，json/yaml 这些格式本身就要求 UTF-8
+    if dialect.bom && matches!(format, OutputFormat::Csv) {
+        writer.write_all("\u{feff}".as_bytes())?;
+    }
+    let writer: Box<dyn RecordWriter + 'a> = match format {
+        OutputFormat::Json => Box::new(JsonWriter::new(writer)),
+        OutputFormat::Yaml => Box::new(YamlWriter::new(writer)),
+        OutputFormat::Toml => Box::new(TomlWriter::new(writer)),
+        OutputFormat::Ndjson => Box::new(NdjsonWriter::new(writer)),
+        OutputFormat::Xml => Box::new(XmlWriter::new(writer)),
+        OutputFormat::Csv => Box::new(CsvWriter::new(writer, dialect)),
+        OutputFormat::Xlsx => Box::new(XlsxWriter::new(writer)),
+        OutputFormat::Parquet => Box::new(ParquetWriter::new(writer)),
+        OutputFormat::Table => Box::new(TableWriter::new(writer, TableOptions::default())),
+    };
+    Ok(writer)
+}
+
+impl CsvDialect {
+    pub fn writer_builder(&self) -> WriterBuilder {
+        let mut builder = WriterBuilder::new();
+        // 表头由 CsvWriter 自己写，这里统一关掉
+        builder
+            .delimiter(self.delimiter)
+            .has_headers(false)
+            .quote(self.quote)
+            .flexible(self.flexible);
+        if let Some(escape) = self.escape {
+            builder.double_quote(false).escape(escape);
+        }
+        builder
+    }
+
+    // 先按 BOM 或者 --encoding 把输入转成 UTF-8 再交给 csv 解析，输入开头的 BOM 会被去掉
+    // 没有 BOM 也没指定编码时原样透传，不会有额外的拷贝
+    pub fn decode<R: Read>(&self, reader: R) -> DecodeReaderBytes<R, Vec<u8>> {
+        DecodeReaderBytesBuilder::new()
+            .encoding(self.encoding)
+            .strip_bom(true)
+            .build(reader)
+    }
+
+    pub fn reader_builder(&self) -> ReaderBuilder {
+        let mut builder = ReaderBuilder::new();
+        builder
+            .delimiter(self.delimiter)
+            .has_headers(self.has_headers)
+            .quote(self.quote)
+            .escape(self.escape)
+            .comment(self.comment)
+            .flexible(self.flexible);
+        builder
+    }
+}
+
+impl Default for CsvDialect {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            has_headers: true,
+            quote: b'"',
+            escape: None,
+            comment: None,
+            flexible: false,
+            encoding: None,
+            bom: false,
+        }
+    }
+}
+
+impl<'a> JsonWriter<'a> {
+    fn new(writer: &'a mut dyn Write) -> Self {
+        Self { writer, count: 0 }
+    }
+}
+
+impl RecordWriter for JsonWriter<'_> {
+    fn write(&mut self, record: &Value) -> Result<()> {
+        let sep: &[u8] = if self.count == 0 { b"[\n" } else { b",\n" };
+        self.writer.write_all(sep)?;
+
+        // 输出和 to_string_pretty 整个数组时保持一致：数组元素要再缩进一层
+        // JSON 字符串里的换行都会被转义，所以按 \n 切分是安全的
+        let content = serde_json::to_vec_pretty(record)?;
+        for (i, line) in content.split(|b| *b == b'\n').enumerate() {
+            if i > 0 {
+                self.writer.write_all(b"\n")?;
+            }
+            self.writer.write_all(b"  ")?;
+            self.writer.write_all(line)?;
+        }
+
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let end: &[u8] = if self.count == 0 { b"[]" } else { b"\n]" };
+        self.writer.write_all(end)?;
+        Ok(())
+    }
+
+    fn resume(&mut self) {
+        self.count = 1;
+    }
+
+    // 续写的记录都以 ",\n" 开头，如果是整个文档的第一条，要换成 "[\n"
+    fn write_encoded(&mut self, encoded: &[u8], records: usize) -> Result<()> {
+        if records == 0 {
+            return Ok(());
+        }
+        if self.count == 0 {
+            self.writer.write_all(b"[\n")?;
+            self.writer.write_all(&encoded[2..])?;
+        } else {
+            self.writer.write_all(encoded)?;
+        }
+        self.count += records;
+        Ok(())
+    }
+}
+
+impl<'a> YamlWriter<'a> {
+    fn new(writer: &'a mut dyn Write) -> Self {
+        Self { writer, count: 0 }
+    }
+}
+
+impl RecordWriter for YamlWriter<'_> {
+    fn write(&mut self, record: &Value) -> Result<()> {
+        // 单个元素的序列拼接起来，和整个序列一次性序列化的结果是一样的
+        serde_yaml::to_writer(&mut *self.writer, &[record])?;
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if self.count == 0 {
+            self.writer.write_all(b"[]\n")?;
+        }
+        Ok(())
+    }
+
+    fn write_encoded(&mut self, encoded: &[u8], records: usize) -> Result<()> {
+        self.writer.write_all(encoded)?;
+        self.count += records;
+        Ok(())
+    }
+}
+
+impl<'a> TomlWriter<'a> {
+    fn new(writer: &'a mut dyn Write) -> Self {
+        Self { writer, count: 0 }
+    }
+}
+
+impl RecordWriter for TomlWriter<'_> {
+    fn write(&mut self, record: &Value) -> Result<()> {
+        if self.count > 0 {
+            self.writer.write_all(b"\n")?;
+        }
+        // TOML 没有 null，空值直接省略这个字段
+        let record = match record {
+            Value::Object(map) if map.values().any(Value::is_null) => Value::Object(
+                map.iter()
+                    .filter(|(_, v)| !v.is_null())
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect(),
+            ),
+            v => v.clone(),
+        };
+        let content = toml::to_string(&TomlRows { row: [&record] })?;
+        self.writer.write_all(content.as_bytes())?;
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn resume(&mut self) {
+        self.count = 1;
+    }
+
+    // 续写的记录前面都有一个空行，文档的第一条不需要
+    fn write_encoded(&mut self, encoded: &[u8], records: usize) -> Result<()> {
+        if records == 0 {
+            return Ok(());
+        }
+        let encoded = if self.count == 0 {
+            &encoded[1..]
+        } else {
+            encoded
+        };
+        self.writer.write_all(encoded)?;
+        self.count += records;
+        Ok(())
+    }
+}
+
+impl<'a> NdjsonWriter<'a> {
+    fn new(writer: &'a mut dyn Write) -> Self {
+        Self { writer }
+    }
+}
+
+impl RecordWriter for NdjsonWriter<'_> {
+    fn write(&mut self, record: &Value) -> Result<()> {
+        serde_json::to_writer(&mut *self.writer, record)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_encoded(&mut self, encoded: &[u8], _records: usize) -> Result<()> {
+        self.writer.write_all(encoded)?;
+        Ok(())
+    }
+}
+
+impl<'a> XmlWriter<'a> {
+    fn new(writer: &'a mut dyn Write) -> Self {
+        Self { writer, count: 0 }
+    }
+}
+
+impl RecordWriter for XmlWriter<'_> {
+    fn write(&mut self, record: &Value) -> Result<()> {
+        if self.count == 0 {
+            self.writer
+                .write_all(b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<records>\n")?;
+        }
+
+        // 列名里可能有空格（比如 Kit Number），不能直接当作标签名，所以放在 name 属性里
+        self.writer.write_all(b"  <record>\n")?;
+        if let Value::Object(map) = record {
+            for (k, v) in map {
+                writeln!(
+                    self.writer,
+                    "    <field name=\"{}\">{}</field>",
+                    escape_xml(k),
+                    escape_xml(&value_to_text(v))
+                )?;
+            }
+        }
+        self.writer.write_all(b"  </record>\n")?;
+
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if self.count == 0 {
+            self.writer
+                .write_all(b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<records>\n")?;
+        }
+        self.writer.write_all(b"</records>\n")?;
+        Ok(())
+    }
+
+    fn resume(&mut self) {
+        self.count = 1;
+    }
+
+    fn write_encoded(&mut self, encoded: &[u8], records: usize) -> Result<()> {
+        if records == 0 {
+            return Ok(());
+        }
+        if self.count == 0 {
+            self.writer
+                .write_all(b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<records>\n")?;
+        }
+        self.writer.write_all(encoded)?;
+        self.count += records;
+        Ok(())
+    }
+}
+
+impl<'a> CsvWriter<'a> {
+    fn new(writer: &'a mut dyn Write, dialect: &CsvDialect) -> Self {
+        let builder = dialect.writer_builder();
+        Self {
+            writer: Some(builder.from_writer(writer)),
+            builder,
+            has_headers: dialect.has_headers,
+            headers: Vec::new(),
+        }
+    }
+
+    fn writer(&mut self) -> &mut csv::Writer<&'a mut dyn Write> {
+        self.writer.as_mut().expect("csv writer is always put back")
+    }
+}
+
+impl RecordWriter for CsvWriter<'_> {
+    fn write_headers(&mut self, headers: &[String]) -> Result<()> {
+        self.headers = headers.to_vec();
+        if self.has_headers {
+            self.writer().write_record(headers)?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, record: &Value) -> Result<()> {
+        // 按表头的顺序取值，没有这个字段就留空
+        let row = self
+            .headers
+            .iter()
+            .map(|h| record.get(h).map(value_to_text).unwrap_or_default());
+        let writer = self.writer.as_mut().expect("csv writer is always put back");
+        writer.write_record(row)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer().flush()?;
+        Ok(())
+    }
+
+    fn resume(&mut self) {
+        self.has_headers = false;
+    }
+
+    fn write_encoded(&mut self, encoded: &[u8], _records: usize) -> Result<()> {
+        // csv::Writer 拿不到里面的 writer，只能先取出来（会先 flush 缓冲区），写完再重新包一层
+        let writer = self.writer.take().expect("csv writer is always put back");
+        let writer = writer.into_inner().map_err(|e| e.into_error())?;
+        writer.write_all(encoded)?;
+        self.writer = Some(self.builder.from_writer(writer));
+        Ok(())
+    }
+}
+
+pub fn value_to_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        v => v.to_string(),
+    }
+}
+
+fn escape_xml(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => ret.push_str("&amp;"),
+            '<' => ret.push_str("&lt;"),
+            '>' => ret.push_str("&gt;"),
+            '"' => ret.push_str("&quot;"),
+            '\'' => ret.push_str("&apos;"),
+            c => ret.push(c),
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CsvSchema;
+
+    #[test]
+    fn test_process_csv_stream_json() -> Result<()> {
+        let mut reader = "a,b\n1,2\n3,4\n".as_bytes();
+        let mut writer = Vec::new();
+        process_csv_stream(
+            &mut reader,
+            &mut writer,
+            OutputFormat::Json,
+            &CsvDialect::default(),
+            &CsvTypes::default(),
+            &CsvQuery::default(),
+        )?;
+
+        let expected: Value = serde_json::from_str(r#"[{"a":"1","b":"2"},{"a":"3","b":"4"}]"#)?;
+        assert_eq!(
+            String::from_utf8(writer)?,
+            serde_json::to_string_pretty(&expected)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_stream_yaml() -> Result<()> {
+        let mut reader = "a,b\n1,2\n3,4\n".as_bytes();
+        let mut writer = Vec::new();
+        process_csv_stream(
+            &mut reader,
+            &mut writer,
+            OutputFormat::Yaml,
+            &CsvDialect::default(),
+            &CsvTypes::default(),
+            &CsvQuery::default(),
+        )?;
+
+        let expected: Value = serde_json::from_str(r#"[{"a":"1","b":"2"},{"a":"3","b":"4"}]"#)?;
+        assert_eq!(
+            String::from_utf8(writer)?,
+            serde_yaml::to_string(&expected)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_stream_empty() -> Result<()> {
+        let mut writer = Vec::new();
+        process_csv_stream(
+            &mut "a,b\n".as_bytes(),
+            &mut writer,
+            OutputFormat::Json,
+            &CsvDialect::default(),
+            &CsvTypes::default(),
+            &CsvQuery::default(),
+        )?;
+        assert_eq!(writer, b"[]");
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_stream_toml() -> Result<()> {
+        let mut reader = "a,Kit Number\n1,2\n3,4\n".as_bytes();
+        let mut writer = Vec::new();
+        process_csv_stream(
+            &mut reader,
+            &mut writer,
+            OutputFormat::Toml,
+            &CsvDialect::default(),
+            &CsvTypes::default(),
+            &CsvQuery::default(),
+        )?;
+
+        let content = String::from_utf8(writer)?;
+        let ret: toml::Table = toml::from_str(&content)?;
+        let rows = ret["row"].as_array().expect("row should be an array");
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1]["Kit Number"].as_str(), Some("4"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_stream_ndjson() -> Result<()> {
+        let mut reader = "a,b\n1,2\n3,4\n".as_bytes();
+        let mut writer = Vec::new();
+        process_csv_stream(
+            &mut reader,
+            &mut writer,
+            OutputFormat::Ndjson,
+            &CsvDialect::default(),
+            &CsvTypes::default(),
+            &CsvQuery::default(),
+        )?;
+        assert_eq!(
+            String::from_utf8(writer)?,
+            "{\"a\":\"1\",\"b\":\"2\"}\n{\"a\":\"3\",\"b\":\"4\"}\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_stream_xml() -> Result<()> {
+        let mut reader = "Kit Number,Name\n10,<A & B>\n".as_bytes();
+        let mut writer = Vec::new();
+        process_csv_stream(
+            &mut reader,
+            &mut writer,
+            OutputFormat::Xml,
+            &CsvDialect::default(),
+            &CsvTypes::default(),
+            &CsvQuery::default(),
+        )?;
+        assert_eq!(
+            String::from_utf8(writer)?,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<records>
+  <record>
+    <field name="Kit Number">10</field>
+    <field name="Name">&lt;A &amp; B&gt;</field>
+  </record>
+</records>
+"#
+        );
+        Ok(())
+    }
+
+    fn convert(input: &str, dialect: &CsvDialect) -> Result<String> {
+        let mut writer = Vec::new();
+        process_csv_stream(
+            &mut input.as_bytes(),
+            &mut writer,
+            OutputFormat::Ndjson,
+            dialect,
+            &CsvTypes::default(),
+            &CsvQuery::default(),
+        )?;
+        Ok(String::from_utf8(writer)?)
+    }
+
+    #[test]
+    fn test_process_csv_stream_delimiter() -> Result<()> {
+        let dialect = CsvDialect {
+            delimiter: b';',
+            ..Default::default()
+        };
+        assert_eq!(
+            convert("a;b\n1,5;2\n", &dialect)?,
+            "{\"a\":\"1,5\",\"b\":\"2\"}\n"
+        );
+
+        let dialect = CsvDialect {
+            delimiter: b'\t',
+            ..Default::default()
+        };
+        assert_eq!(
+            convert("a\tb\n1\t2\n", &dialect)?,
+            "{\"a\":\"1\",\"b\":\"2\"}\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_stream_no_header() -> Result<()> {
+        let dialect = CsvDialect {
+            has_headers: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            convert("a,b\n1,2\n", &dialect)?,
+            "{\"col0\":\"a\",\"col1\":\"b\"}\n{\"col0\":\"1\",\"col1\":\"2\"}\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_stream_quote_escape_comment() -> Result<()> {
+        let dialect = CsvDialect {
+            quote: b'\'',
+            escape: Some(b'\\'),
+            comment: Some(b'#'),
+            ..Default::default()
+        };
+        assert_eq!(
+            convert("a,b\n# skipped\n'x,\\'y',2\n", &dialect)?,
+            "{\"a\":\"x,'y\",\"b\":\"2\"}\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_stream_encoding() -> Result<()> {
+        let expected = "{\"号码\":\"1\",\"名字\":\"布冯\"}\n";
+
+        // 带 BOM 的 UTF-16 不需要指定编码
+        let mut utf16 = vec![0xff, 0xfe];
+        utf16.extend(
+            "名字,号码\n布冯,1\n"
+                .encode_utf16()
+                .flat_map(u16::to_le_bytes),
+        );
+        let mut writer = Vec::new();
+        process_csv_stream(
+            &mut utf16.as_slice(),
+            &mut writer,
+            OutputFormat::Ndjson,
+            &CsvDialect::default(),
+            &CsvTypes::default(),
+            &CsvQuery::default(),
+        )?;
+        assert_eq!(String::from_utf8(writer)?, expected);
+
+        // GBK 没有 BOM，需要用 --encoding 指定
+        let (gbk, _, _) = encoding_rs::GBK.encode("名字,号码\n布冯,1\n");
+        let dialect = CsvDialect {
+            encoding: Some(encoding_rs::GBK),
+            ..Default::default()
+        };
+        let mut writer = Vec::new();
+        process_csv_stream(
+            &mut gbk.as_ref(),
+            &mut writer,
+            OutputFormat::Ndjson,
+            &dialect,
+            &CsvTypes::default(),
+            &CsvQuery::default(),
+        )?;
+        assert_eq!(String::from_utf8(writer)?, expected);
+
+        // UTF-8 的 BOM 会被去掉，不会混进第一列的列名里
+        assert_eq!(
+            convert("\u{feff}a,b\n1,2\n", &CsvDialect::default())?,
+            "{\"a\":\"1\",\"b\":\"2\"}\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_file() -> Result<()> {
+        let output = std::env::temp_dir().join(format!("rcli-{}.ndjson", std::process::id()));
+        let output = output.to_string_lossy().to_string();
+        process_csv(
+            "assets/juventus.csv",
+            output.clone(),
+            OutputFormat::Ndjson,
+            &CsvDialect::default(),
+            &CsvTypes::default(),
+            &CsvQuery::default(),
+            None,
+        )?;
+        let content = std::fs::read_to_string(&output)?;
+        std::fs::remove_file(&output)?;
+        assert_eq!(content.lines().count(), 27);
+        assert!(content.starts_with("{\"DOB\":\"Apr 18, 1990 (29)\""));
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_stream_bom() -> Result<()> {
+        let dialect = CsvDialect {
+            bom: true,
+            ..Default::default()
+        };
+        for (format, expected) in [
+            (OutputFormat::Csv, "\u{feff}a,b\n1,2\n"),
+            (OutputFormat::Ndjson, "{\"a\":\"1\",\"b\":\"2\"}\n"),
+        ] {
+            let mut writer = Vec::new();
+            process_csv_stream(
+                &mut "a,b\n1,2\n".as_bytes(),
+                &mut writer,
+                format,
+                &dialect,
+                &CsvTypes::default(),
+                &CsvQuery::default(),
+            )?;
+            assert_eq!(String::from_utf8(writer)?, expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_stream_flexible() -> Result<()> {
+        assert!(convert("a,b\n1,2,3\n", &CsvDialect::default()).is_err());
+
+        let dialect = CsvDialect {
+            flexible: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            convert("a,b\n1\n1,2,3\n", &dialect)?,
+            "{\"a\":\"1\"}\n{\"a\":\"1\",\"b\":\"2\",\"col2\":\"3\"}\n"
+        );
+
+        // 后面的行多出来的列在 csv 里也要有表头，生成的列名不能和已有的列名重复
+        let mut writer = Vec::new();
+        process_csv_stream(
+            &mut "col3,b\n1,2\n1,2,3,4\n".as_bytes(),
+            &mut writer,
+            OutputFormat::Csv,
+            &dialect,
+            &CsvTypes::default(),
+            &CsvQuery::default(),
+        )?;
+        assert_eq!(
+            String::from_utf8(writer)?,
+            "col3,b,col2,col3_1\n1,2,,\n1,2,3,4\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_stream_types() -> Result<()> {
+        let types = CsvTypes {
+            infer: true,
+            schema: serde_yaml::from_str("Name: string")?,
+        };
+        let mut reader = "Name,Kit Number,Rating,Active,Club\n10,10,8.5,true,\n".as_bytes();
+        let mut writer = Vec::new();
+        let dialect = CsvDialect::default();
+        process_csv_stream(
+            &mut reader,
+            &mut writer,
+            OutputFormat::Ndjson,
+            &dialect,
+            &types,
+            &CsvQuery::default(),
+        )?;
+        assert_eq!(
+            String::from_utf8(writer)?,
+            "{\"Active\":true,\"Club\":null,\"Kit Number\":10,\"Name\":\"10\",\"Rating\":8.5}\n"
+        );
+
+        // TOML 不支持 null，对应的字段会被省略
+        let mut reader = "a,b\n1,\n".as_bytes();
+        let mut writer = Vec::new();
+        process_csv_stream(
+            &mut reader,
+            &mut writer,
+            OutputFormat::Toml,
+            &dialect,
+            &types,
+            &CsvQuery::default(),
+        )?;
+        assert_eq!(String::from_utf8(writer)?, "[[row]]\na = 1\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_stream_schema_error() -> Result<()> {
+        let types = CsvTypes {
+            infer: false,
+            schema: serde_yaml::from_str("Kit Number: integer")?,
+        };
+        let mut reader = "Name,Kit Number\nA,1\nB,ten\n".as_bytes();
+        let mut writer = Vec::new();
+        let err = process_csv_stream(
+            &mut reader,
+            &mut writer,
+            OutputFormat::Json,
+            &CsvDialect::default(),
+            &types,
+            &CsvQuery::default(),
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "Invalid value at line 3");
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_stream_csv() -> Result<()> {
+        let types = CsvTypes::default();
+        let mut reader = "a,b\n\"x,y\",2\n".as_bytes();
+        let mut writer = Vec::new();
+        process_csv_stream(
+            &mut reader,
+            &mut writer,
+            OutputFormat::Csv,
+            &CsvDialect::default(),
+            &types,
+            &CsvQuery::default(),
+        )?;
+        assert_eq!(String::from_utf8(writer)?, "a,b\n\"x,y\",2\n");
+
+        // 输出沿用输入的 dialect
+        let dialect = CsvDialect {
+            delimiter: b';',
+            has_headers: false,
+            ..Default::default()
+        };
+        let mut reader = "a;b\nx,y;2\n".as_bytes();
+        let mut writer = Vec::new();
+        process_csv_stream(
+            &mut reader,
+            &mut writer,
+            OutputFormat::Csv,
+            &dialect,
+            &types,
+            &CsvQuery::default(),
+        )?;
+        assert_eq!(String::from_utf8(writer)?, "a;b\nx,y;2\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_stream_query() -> Result<()> {
+        let mut reader =
+            "Name,Position,Kit Number\nA,Goalkeeper,1\nB,Defender,3\nC,Goalkeeper,12\n".as_bytes();
+        let mut writer = Vec::new();
+        let query = CsvQuery {
+            select: vec!["Kit Number".into(), "Name".into()],
+            rename: vec![("Kit Number".into(), "kit".into())],
+            filter: Some("Position == 'Goalkeeper' && `Kit Number` > 5".parse()?),
+        };
+        process_csv_stream(
+            &mut reader,
+            &mut writer,
+            OutputFormat::Csv,
+            &CsvDialect::default(),
+            &serde_yaml::from_str::<CsvSchema>("Kit Number: integer").map(|schema| CsvTypes {
+                infer: false,
+                schema,
+            })?,
+            &query,
+        )?;
+        assert_eq!(String::from_utf8(writer)?, "kit,Name\n12,C\n");
+        Ok(())
+    }
 }
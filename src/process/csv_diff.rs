@@ -0,0 +1,339 @@
+use super::CsvDialect;
+use crate::cli::OutputFormat;
+use anyhow::{anyhow, bail, Result};
+use csv::StringRecord;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+};
+
+/// Rows added, removed and modified between two CSV files matched by key columns
+#[derive(Debug, Default, Serialize)]
+pub struct CsvDiff {
+    pub added_columns: Vec<String>,
+    pub removed_columns: Vec<String>,
+    pub added: Vec<Map<String, Value>>,
+    pub removed: Vec<Map<String, Value>>,
+    pub modified: Vec<ModifiedRow>,
+    // 所有列按第一次出现的顺序排列，只在文本输出时用来保持列的顺序
+    #[serde(skip)]
+    columns: Vec<String>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct ModifiedRow {
+    pub key: Map<String, Value>,
+    pub changes: Vec<CellChange>,
+}
+
+/// A cell that differs, only columns present in both files are compared
+#[derive(Debug, Serialize, PartialEq)]
+pub struct CellChange {
+    pub column: String,
+    pub old: String,
+    pub new: String,
+}
+
+// 一个文件读出来的表头和所有行，index 是 key -> 行号
+struct Table {
+    headers: StringRecord,
+    rows: Vec<StringRecord>,
+    keys: Vec<usize>,
+    index: HashMap<Vec<String>, usize>,
+}
+
+/// Compare `old` and `new` row by row using the key columns, `format` None means a human readable report
+pub fn process_csv_diff(
+    old: &mut dyn Read,
+    new: &mut dyn Read,
+    writer: &mut dyn Write,
+    format: Option<OutputFormat>,
+    dialect: &CsvDialect,
+    keys: &[String],
+) -> Result<CsvDiff> {
+    let old = Table::read(old, dialect, keys).map_err(|e| e.context("old file"))?;
+    let new = Table::read(new, dialect, keys).map_err(|e| e.context("new file"))?;
+    let diff = diff_tables(&old, &new);
+
+    match format {
+        None => write_human(&diff, writer)?,
+        Some(OutputFormat::Json) => {
+            serde_json::to_writer_pretty(&mut *writer, &diff)?;
+            writer.write_all(b"\n")?;
+        }
+        Some(OutputFormat::Yaml) => serde_yaml::to_writer(&mut *writer, &diff)?,
+        Some(format) => bail!("Unsupported diff format: {}", format),
+    }
+    Ok(diff)
+}
+
+impl Table {
+    fn read(reader: &mut dyn Read, dialect: &CsvDialect, keys: &[String]) -> Result<Self> {
+        let mut reader = dialect.reader_builder().from_reader(dialect.decode(reader));
+        let mut headers = if dialect.has_headers {
+            reader.headers()?.clone()
+        } else {
+            StringRecord::new()
+        };
+        let mut rows = Vec::new();
+        for record in reader.records() {
+            let record = record?;
+            for i in headers.len()..record.len() {
+                headers.push_field(&format!("col{}", i));
+            }
+            rows.push(record);
+        }
+        let keys = keys
+            .iter()
+            .map(|k| {
+                headers
+                    .iter()
+                    .position(|h| h == k)
+                    .ok_or_else(|| anyhow!("Unknown key column: {}", k))
+            })
+            .collect::<Result<_>>()?;
+        let mut table = Self {
+            headers,
+            rows,
+            keys,
+            index: HashMap::new(),
+        };
+        table.build_index()?;
+        Ok(table)
+    }
+
+    fn key(&self, row: &StringRecord) -> Vec<String> {
+        self.keys
+            .iter()
+            .map(|i| row.get(*i).unwrap_or_default().to_string())
+            .collect()
+    }
+
+    // key 重复时报错，否则没法确定该和哪一行比较
+    fn build_index(&mut self) -> Result<()> {
+        self.index.reserve(self.rows.len());
+        for (i, row) in self.rows.iter().enumerate() {
+            let key = self.key(row);
+            if self.index.insert(key.clone(), i).is_some() {
+                let line = row.position().map(|p| p.line()).unwrap_or_default();
+                bail!("Duplicate key {} at line {}", key.join(","), line);
+            }
+        }
+        Ok(())
+    }
+
+    fn get<'a>(&'a self, row: &'a StringRecord, column: &str) -> Option<&'a str> {
+        let i = self.headers.iter().position(|h| h == column)?;
+        Some(row.get(i).unwrap_or_default())
+    }
+
+    fn to_map(&self, row: &StringRecord) -> Map<String, Value> {
+        self.headers
+            .iter()
+            .zip(row.iter())
+            .map(|(h, v)| (h.to_string(), v.into()))
+            .collect()
+    }
+}
+
+fn diff_tables(old: &Table, new: &Table) -> CsvDiff {
+    let mut diff = CsvDiff::default();
+    for h in old.headers.iter().chain(new.headers.iter()) {
+        if !diff.columns.iter().any(|c| c == h) {
+            diff.columns.push(h.to_string());
+        }
+    }
+    diff.added_columns = new
+        .headers
+        .iter()
+        .filter(|h| !old.headers.iter().any(|o| o == *h))
+        .map(String::from)
+        .collect();
+    diff.removed_columns = old
+        .headers
+        .iter()
+        .filter(|h| !new.headers.iter().any(|n| n == *h))
+        .map(String::from)
+        .collect();
+
+    // 新增和修改按新文件的顺序，删除按旧文件的顺序
+    let mut matched = vec![false; old.rows.len()];
+    for row in &new.rows {
+        let key = new.key(row);
+        let Some(&i) = old.index.get(&key) else {
+            diff.added.push(new.to_map(row));
+            continue;
+        };
+        matched[i] = true;
+        let old_row = &old.rows[i];
+        // 新增和删除的列已经在 added_columns 和 removed_columns 里了，不算作行的修改
+        let changes: Vec<CellChange> = diff
+            .columns
+            .iter()
+            .filter_map(|column| {
+                let before = old.get(old_row, column)?;
+                let after = new.get(row, column)?;
+                (before != after).then(|| CellChange {
+                    column: column.clone(),
+                    old: before.to_string(),
+                    new: after.to_string(),
+                })
+            })
+            .collect();
+        if !changes.is_empty() {
+            let key = new
+                .keys
+                .iter()
+                .zip(key)
+                .map(|(i, v)| (new.headers[*i].to_string(), v.into()))
+                .collect();
+            diff.modified.push(ModifiedRow { key, changes });
+        }
+    }
+    diff.removed = old
+        .rows
+        .iter()
+        .zip(matched)
+        .filter(|(_, matched)| !matched)
+        .map(|(row, _)| old.to_map(row))
+        .collect();
+    diff
+}
+
+fn write_human(diff: &CsvDiff, writer: &mut dyn Write) -> Result<()> {
+    if !diff.added_columns.is_empty() {
+        writeln!(writer, "Added columns: {}", diff.added_columns.join(", "))?;
+    }
+    if !diff.removed_columns.is_empty() {
+        writeln!(
+            writer,
+            "Removed columns: {}",
+            diff.removed_columns.join(", ")
+        )?;
+    }
+    for row in &diff.added {
+        writeln!(writer, "+ {}", diff.format_row(row))?;
+    }
+    for row in &diff.removed {
+        writeln!(writer, "- {}", diff.format_row(row))?;
+    }
+    for row in &diff.modified {
+        writeln!(writer, "~ {}", diff.format_row(&row.key))?;
+        for change in &row.changes {
+            writeln!(
+                writer,
+                "    {}: {:?} -> {:?}",
+                change.column, change.old, change.new
+            )?;
+        }
+    }
+    writeln!(
+        writer,
+        "{} added, {} removed, {} modified",
+        diff.added.len(),
+        diff.removed.len(),
+        diff.modified.len()
+    )?;
+    Ok(())
+}
+
+impl CsvDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added_columns.is_empty()
+            && self.removed_columns.is_empty()
+            && self.added.is_empty()
+            && self.removed.is_empty()
+            && self.modified.is_empty()
+    }
+
+    // 按原来的列顺序输出 列名=值
+    fn format_row(&self, row: &Map<String, Value>) -> String {
+        self.columns
+            .iter()
+            .filter_map(|c| Some(format!("{}={}", c, row.get(c)?.as_str()?)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD: &str = "Name,Position,Kit Number\n\
+        Wojciech Szczesny,Goalkeeper,1\n\
+        Mattia Perin,Goalkeeper,37\n\
+        Mario Mandzukic,Centre-Forward,17\n";
+
+    const NEW: &str = "Name,Position,Kit Number,Club\n\
+        Wojciech Szczesny,Goalkeeper,1,Juventus\n\
+        Mattia Perin,Goalkeeper,22,Juventus\n\
+        Moise Kean,Centre-Forward,18,Juventus\n";
+
+    fn diff(old: &str, new: &str, format: Option<OutputFormat>) -> Result<(CsvDiff, String)> {
+        let mut writer = Vec::new();
+        let diff = process_csv_diff(
+            &mut old.as_bytes(),
+            &mut new.as_bytes(),
+            &mut writer,
+            format,
+            &CsvDialect::default(),
+            &["Name".to_string()],
+        )?;
+        Ok((diff, String::from_utf8(writer)?))
+    }
+
+    #[test]
+    fn test_process_csv_diff_human() -> Result<()> {
+        let (ret, output) = diff(OLD, NEW, None)?;
+        assert_eq!(ret.added.len(), 1);
+        assert_eq!(ret.removed.len(), 1);
+        assert_eq!(ret.modified.len(), 1);
+        assert_eq!(
+            output,
+            "Added columns: Club\n\
+             + Name=Moise Kean, Position=Centre-Forward, Kit Number=18, Club=Juventus\n\
+             - Name=Mario Mandzukic, Position=Centre-Forward, Kit Number=17\n\
+             ~ Name=Mattia Perin\n\
+            \x20   Kit Number: \"37\" -> \"22\"\n\
+             1 added, 1 removed, 1 modified\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_diff_json() -> Result<()> {
+        let (_, output) = diff(OLD, OLD, Some(OutputFormat::Json))?;
+        let value: Value = serde_json::from_str(&output)?;
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "added_columns": [], "removed_columns": [],
+                "added": [], "removed": [], "modified": [],
+            })
+        );
+
+        let (ret, output) = diff(OLD, NEW, Some(OutputFormat::Yaml))?;
+        assert!(!ret.is_empty());
+        let value: Value = serde_yaml::from_str(&output)?;
+        assert_eq!(
+            value["modified"],
+            serde_json::json!([{
+                "key": {"Name": "Mattia Perin"},
+                "changes": [{"column": "Kit Number", "old": "37", "new": "22"}],
+            }])
+        );
+        assert!(diff(OLD, NEW, Some(OutputFormat::Toml)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_diff_key_errors() {
+        let dup = "Name,Kit Number\nA,1\nA,2\n";
+        let err = diff(dup, OLD, None).unwrap_err();
+        assert_eq!(format!("{:#}", err), "old file: Duplicate key A at line 3");
+        assert!(diff("Player\nA\n", OLD, None).is_err());
+    }
+}
@@ -0,0 +1,337 @@
+use super::{record_writer, CsvDialect};
+use crate::{cli::OutputFormat, get_reader, JoinKind};
+use anyhow::{anyhow, bail, Result};
+use csv::StringRecord;
+use encoding_rs_io::DecodeReaderBytes;
+use serde_json::{Map, Value};
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+};
+
+/// Join two CSV files on the key columns, columns of the right file follow the columns of the left file
+pub fn process_csv_join(
+    left: &mut dyn Read,
+    right: &mut dyn Read,
+    writer: &mut dyn Write,
+    format: OutputFormat,
+    dialect: &CsvDialect,
+    on: &[String],
+    how: JoinKind,
+) -> Result<()> {
+    // 右边的文件整个读进内存按 key 建索引，左边的文件逐行处理
+    let mut right = dialect.reader_builder().from_reader(dialect.decode(right));
+    let (right_headers, first) = read_headers(&mut right, dialect)?;
+    let right_keys = positions(&right_headers, on)?;
+    let mut right_rows = Vec::new();
+    let mut index: HashMap<Vec<String>, Vec<usize>> = HashMap::new();
+    for record in first.into_iter().map(Ok).chain(right.records()) {
+        let record = record?;
+        index
+            .entry(key(&record, &right_keys))
+            .or_default()
+            .push(right_rows.len());
+        right_rows.push(record);
+    }
+
+    let mut left = dialect.reader_builder().from_reader(dialect.decode(left));
+    let (left_headers, first) = read_headers(&mut left, dialect)?;
+    let left_keys = positions(&left_headers, on)?;
+
+    // 右边的 key 列和左边重复，不再输出；其他同名的列加上 _right 后缀
+    let right_columns: Vec<(usize, String)> = right_headers
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !right_keys.contains(i))
+        .map(|(i, h)| {
+            let name = if left_headers.iter().any(|l| l == h) {
+                format!("{}_right", h)
+            } else {
+                h.to_string()
+            };
+            (i, name)
+        })
+        .collect();
+    let mut headers: Vec<String> = left_headers.iter().map(String::from).collect();
+    headers.extend(right_columns.iter().map(|(_, name)| name.clone()));
+
+    let mut writer = record_writer(writer, format, dialect)?;
+    writer.write_headers(&headers)?;
+
+    let mut matched = vec![false; right_rows.len()];
+    for record in first.into_iter().map(Ok).chain(left.records()) {
+        let record = record?;
+        let rows = index.get(&key(&record, &left_keys));
+        match rows {
+            Some(rows) => {
+                for i in rows {
+                    matched[*i] = true;
+                    let row = join_row(
+                        &left_headers,
+                        Some(&record),
+                        &right_columns,
+                        &right_rows[*i],
+                    );
+                    writer.write(&Value::Object(row))?;
+                }
+            }
+            None if how != JoinKind::Inner => {
+                let mut row = to_map(&left_headers, &record);
+                for (_, name) in &right_columns {
+                    row.insert(name.clone(), Value::Null);
+                }
+                writer.write(&Value::Object(row))?;
+            }
+            None => {}
+        }
+    }
+
+    // outer join 最后输出右边没有匹配上的行，key 列用右边的值填上
+    if how == JoinKind::Outer {
+        for (record, _) in right_rows.iter().zip(matched).filter(|(_, m)| !m) {
+            let mut row = join_row(&left_headers, None, &right_columns, record);
+            for (l, r) in left_keys.iter().zip(&right_keys) {
+                let value = record.get(*r).unwrap_or_default();
+                row.insert(left_headers[*l].to_string(), value.into());
+            }
+            writer.write(&Value::Object(row))?;
+        }
+    }
+    writer.finish()
+}
+
+// 一个输入文件的 reader、表头，以及没有表头时读出来的第一行
+type ConcatInput = (
+    csv::Reader<DecodeReaderBytes<Box<dyn Read>, Vec<u8>>>,
+    StringRecord,
+    Option<StringRecord>,
+);
+
+/// Append CSV files one after another, the output has the union of all headers and missing cells are empty
+pub fn process_csv_concat(
+    inputs: &[String],
+    writer: &mut dyn Write,
+    format: OutputFormat,
+    dialect: &CsvDialect,
+) -> Result<()> {
+    if inputs.iter().filter(|i| *i == "-").count() > 1 {
+        bail!("stdin can only be used once");
+    }
+    let open = |input: &str| -> Result<ConcatInput> {
+        let mut reader = dialect
+            .reader_builder()
+            .from_reader(dialect.decode(get_reader(input)?));
+        let (headers, first) = read_headers(&mut reader, dialect)?;
+        Ok((reader, headers, first))
+    };
+
+    // 先逐个文件读出表头，合并出完整的表头才能开始输出；读完表头就关掉，输出数据行时再重新打开，
+    // 这样同时只打开一个文件。stdin 没法重新读，所以它的 reader 留着接着用
+    let mut headers: Vec<String> = Vec::new();
+    let mut stdin = None;
+    for input in inputs {
+        let file = open(input)?;
+        for h in file.1.iter() {
+            if !headers.iter().any(|c| c == h) {
+                headers.push(h.to_string());
+            }
+        }
+        if input == "-" {
+            stdin = Some(file);
+        }
+    }
+
+    let mut writer = record_writer(writer, format, dialect)?;
+    writer.write_headers(&headers)?;
+    for input in inputs {
+        let (mut reader, file_headers, first) = match stdin.take() {
+            Some(file) if input == "-" => file,
+            other => {
+                stdin = other;
+                open(input)?
+            }
+        };
+        for record in first.into_iter().map(Ok).chain(reader.records()) {
+            let mut row = to_map(&file_headers, &record?);
+            for h in &headers {
+                row.entry(h.clone()).or_insert(Value::Null);
+            }
+            writer.write(&Value::Object(row))?;
+        }
+    }
+    writer.finish()
+}
+
+// 读出表头；没有表头时按第一行的列数生成 col0..colN，并把第一行还回去
+pub(super) fn read_headers<R: Read>(
+    reader: &mut csv::Reader<R>,
+    dialect: &CsvDialect,
+) -> Result<(StringRecord, Option<StringRecord>)> {
+    if dialect.has_headers {
+        return Ok((reader.headers()?.clone(), None));
+    }
+    let mut record = StringRecord::new();
+    if !reader.read_record(&mut record)? {
+        return Ok((StringRecord::new(), None));
+    }
+    let headers = (0..record.len()).map(|i| format!("col{}", i)).collect();
+    Ok((headers, Some(record)))
+}
+
+pub(super) fn positions(headers: &StringRecord, columns: &[String]) -> Result<Vec<usize>> {
+    columns
+        .iter()
+        .map(|c| {
+            headers
+                .iter()
+                .position(|h| h == c)
+                .ok_or_else(|| anyhow!("Unknown column: {}", c))
+        })
+        .collect()
+}
+
+fn key(record: &StringRecord, positions: &[usize]) -> Vec<String> {
+    positions
+        .iter()
+        .map(|i| record.get(*i).unwrap_or_default().to_string())
+        .collect()
+}
+
+// 按表头把一行转成 Map，flexible 模式下多出来的单元格会被忽略
+pub(super) fn to_map(headers: &StringRecord, record: &StringRecord) -> Map<String, Value> {
+    headers
+        .iter()
+        .zip(
+            record
+                .iter()
+                .map(Value::from)
+                .chain(std::iter::repeat(Value::Null)),
+        )
+        .map(|(h, v)| (h.to_string(), v))
+        .collect()
+}
+
+fn join_row(
+    left_headers: &StringRecord,
+    left: Option<&StringRecord>,
+    right_columns: &[(usize, String)],
+    right: &StringRecord,
+) -> Map<String, Value> {
+    let mut row = match left {
+        Some(left) => to_map(left_headers, left),
+        None => left_headers
+            .iter()
+            .map(|h| (h.to_string(), Value::Null))
+            .collect(),
+    };
+    for (i, name) in right_columns {
+        let value = right.get(*i).map_or(Value::Null, Value::from);
+        row.insert(name.clone(), value);
+    }
+    row
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEFT: &str = "Name,Position\n\
+        Wojciech Szczesny,Goalkeeper\n\
+        Mattia Perin,Goalkeeper\n\
+        Mario Mandzukic,Centre-Forward\n";
+
+    const RIGHT: &str = "Name,Kit Number,Position\n\
+        Mattia Perin,37,GK\n\
+        Wojciech Szczesny,1,GK\n\
+        Moise Kean,18,CF\n";
+
+    fn join(how: JoinKind) -> Result<String> {
+        let mut writer = Vec::new();
+        process_csv_join(
+            &mut LEFT.as_bytes(),
+            &mut RIGHT.as_bytes(),
+            &mut writer,
+            OutputFormat::Csv,
+            &CsvDialect::default(),
+            &["Name".to_string()],
+            how,
+        )?;
+        Ok(String::from_utf8(writer)?)
+    }
+
+    #[test]
+    fn test_process_csv_join() -> Result<()> {
+        let header = "Name,Position,Kit Number,Position_right\n";
+        let inner = "Wojciech Szczesny,Goalkeeper,1,GK\nMattia Perin,Goalkeeper,37,GK\n";
+        assert_eq!(join(JoinKind::Inner)?, format!("{}{}", header, inner));
+        assert_eq!(
+            join(JoinKind::Left)?,
+            format!("{}{}Mario Mandzukic,Centre-Forward,,\n", header, inner)
+        );
+        assert_eq!(
+            join(JoinKind::Outer)?,
+            format!(
+                "{}{}Mario Mandzukic,Centre-Forward,,\nMoise Kean,,18,CF\n",
+                header, inner
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_join_duplicate_keys() -> Result<()> {
+        let right = "Name,Season\nMattia Perin,2018\nMattia Perin,2019\n";
+        let mut writer = Vec::new();
+        process_csv_join(
+            &mut LEFT.as_bytes(),
+            &mut right.as_bytes(),
+            &mut writer,
+            OutputFormat::Csv,
+            &CsvDialect::default(),
+            &["Name".to_string()],
+            JoinKind::Inner,
+        )?;
+        assert_eq!(
+            String::from_utf8(writer)?,
+            "Name,Position,Season\n\
+             Mattia Perin,Goalkeeper,2018\n\
+             Mattia Perin,Goalkeeper,2019\n"
+        );
+
+        let err = process_csv_join(
+            &mut LEFT.as_bytes(),
+            &mut right.as_bytes(),
+            &mut Vec::new(),
+            OutputFormat::Csv,
+            &CsvDialect::default(),
+            &["Club".to_string()],
+            JoinKind::Inner,
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "Unknown column: Club");
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_concat() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let a = dir.path().join("a.csv");
+        let b = dir.path().join("b.csv");
+        std::fs::write(&a, "Name,Position\nA,Goalkeeper\n")?;
+        std::fs::write(&b, "Name,Kit Number\nB,10\n")?;
+        let inputs = [a, b].map(|p| p.to_string_lossy().to_string());
+
+        let mut writer = Vec::new();
+        process_csv_concat(
+            &inputs,
+            &mut writer,
+            OutputFormat::Csv,
+            &CsvDialect::default(),
+        )?;
+        assert_eq!(
+            String::from_utf8(writer)?,
+            "Name,Position,Kit Number\nA,Goalkeeper,\nB,,10\n"
+        );
+        Ok(())
+    }
+}
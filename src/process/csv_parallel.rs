@@ -0,0 +1,413 @@
+use super::{
+    csv_convert::record_to_value, record_writer, BoundQuery, CsvDialect, CsvQuery, CsvTypes,
+};
+use crate::cli::OutputFormat;
+use anyhow::{bail, Result};
+use csv::{Position, StringRecord};
+use rayon::prelude::*;
+use std::{
+    io::{Read, Write},
+    mem,
+};
+
+// 每个分块的大致字节数，以及每轮最多同时在内存里的分块数（线程数的倍数）
+const CHUNK_BYTES: usize = 1 << 18;
+const CHUNKS_PER_THREAD: usize = 4;
+// 每次从输入读取的字节数
+const READ_BYTES: usize = 1 << 16;
+
+/// Convert CSV on a thread pool: the raw input is split into chunks at record
+/// boundaries, each chunk is parsed, converted and encoded on its own thread, and
+/// the encoded chunks are written back in the original order. `jobs == 0` uses one
+/// thread per CPU.
+pub fn process_csv_parallel(
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    format: OutputFormat,
+    dialect: &CsvDialect,
+    types: &CsvTypes,
+    query: &CsvQuery,
+    jobs: usize,
+) -> Result<()> {
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(jobs).build()?;
+    let mut splitter = ChunkSplitter::new(dialect.decode(reader), dialect);
+    let mut writer = record_writer(writer, format, dialect)?;
+
+    let batch = pool.current_num_threads() * CHUNKS_PER_THREAD;
+    let mut bound: Option<(BoundQuery, usize)> = None;
+    loop {
+        // 先顺序切出一批分块，只找记录的边界，不做完整的解析
+        let mut chunks = Vec::with_capacity(batch);
+        while chunks.len() < batch {
+            match splitter.next_chunk()? {
+                Some(chunk) => chunks.push(chunk),
+                None => break,
+            }
+        }
+
+        // 表头（或者没有表头时的列数）由第一条记录决定，workers 解析之前要先知道
+        let skip_header = bound.is_none() && dialect.has_headers;
+        if bound.is_none() {
+            let mut first = StringRecord::new();
+            if let Some(chunk) = chunks.first() {
+                dialect
+                    .reader_builder()
+                    .has_headers(false)
+                    .from_reader(chunk.data.as_slice())
+                    .read_record(&mut first)?;
+            }
+            let headers: StringRecord = if dialect.has_headers {
+                first
+            } else if first.is_empty() {
+                break;
+            } else {
+                (0..first.len()).map(|i| format!("col{}", i)).collect()
+            };
+            let q = query.bind(&headers)?;
+            writer.write_headers(&q.headers())?;
+            bound = Some((q, headers.len()));
+        }
+        if chunks.is_empty() {
+            break;
+        }
+        let (query, width) = bound.as_ref().expect("query is bound above");
+
+        // collect 会保持分块原来的顺序，出错时返回第一个出错的分块的错误
+        let encoded = pool.install(|| {
+            chunks
+                .par_iter()
+                .enumerate()
+                .map(|(i, chunk)| {
+                    let skip = skip_header && i == 0;
+                    encode_chunk(chunk, skip, *width, format, dialect, query, types)
+                })
+                .collect::<Result<Vec<_>>>()
+        })?;
+        for (buf, records) in encoded {
+            writer.write_encoded(&buf, records)?;
+        }
+    }
+
+    writer.finish()
+}
+
+// 一段以完整记录结尾的原始输入，line 和 byte 是它前面的换行数和字节数，用来还原记录的位置
+struct Chunk {
+    data: Vec<u8>,
+    line: u64,
+    byte: u64,
+}
+
+// 和 csv-core 一样跟踪引号，只有不在引号里的换行才是记录的边界
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScanState {
+    StartRecord,
+    StartField,
+    InField,
+    InQuotedField,
+    InEscapedQuote,
+    EndQuotedField,
+    InComment,
+}
+
+struct ChunkSplitter<R> {
+    reader: R,
+    buf: Vec<u8>,
+    state: ScanState,
+    // buf 里最后一个记录边界的位置，0 表示还没有找到
+    boundary: usize,
+    line: u64,
+    byte: u64,
+    eof: bool,
+    delimiter: u8,
+    quote: u8,
+    escape: Option<u8>,
+    comment: Option<u8>,
+}
+
+impl<R: Read> ChunkSplitter<R> {
+    fn new(reader: R, dialect: &CsvDialect) -> Self {
+        Self {
+            reader,
+            buf: Vec::new(),
+            state: ScanState::StartRecord,
+            boundary: 0,
+            line: 0,
+            byte: 0,
+            eof: false,
+            delimiter: dialect.delimiter,
+            quote: dialect.quote,
+            escape: dialect.escape,
+            comment: dialect.comment,
+        }
+    }
+
+    // 至少读够 CHUNK_BYTES 再在最后一个记录边界处切开，剩下的留给下一个分块
+    fn next_chunk(&mut self) -> Result<Option<Chunk>> {
+        while !self.eof && (self.buf.len() < CHUNK_BYTES || self.boundary == 0) {
+            let start = self.buf.len();
+            self.buf.resize(start + READ_BYTES, 0);
+            let n = self.reader.read(&mut self.buf[start..])?;
+            self.buf.truncate(start + n);
+            if n == 0 {
+                self.eof = true;
+            } else {
+                self.scan(start);
+            }
+        }
+
+        let end = if self.eof {
+            self.buf.len()
+        } else {
+            self.boundary
+        };
+        if end == 0 {
+            return Ok(None);
+        }
+        let rest = self.buf.split_off(end);
+        let data = mem::replace(&mut self.buf, rest);
+        // 最后一个边界之后的内容里不会再有边界
+        self.boundary = 0;
+
+        let chunk = Chunk {
+            line: self.line,
+            byte: self.byte,
+            data,
+        };
+        self.line += chunk.data.iter().filter(|b| **b == b'\n').count() as u64;
+        self.byte += chunk.data.len() as u64;
+        Ok(Some(chunk))
+    }
+
+    fn scan(&mut self, start: usize) {
+        use ScanState::*;
+        for i in start..self.buf.len() {
+            let b = self.buf[i];
+            self.state = match self.state {
+                InComment if b == b'\n' => StartRecord,
+                InComment => InComment,
+                InQuotedField if b == self.quote => EndQuotedField,
+                InQuotedField if Some(b) == self.escape => InEscapedQuote,
+                InQuotedField => InQuotedField,
+                InEscapedQuote => InQuotedField,
+                StartRecord if Some(b) == self.comment => InComment,
+                StartRecord | StartField if b == self.quote => InQuotedField,
+                // 两个连续的引号表示引号本身
+                EndQuotedField if b == self.quote => InQuotedField,
+                _ if b == self.delimiter => StartField,
+                _ if b == b'\n' => StartRecord,
+                _ => InField,
+            };
+            if self.state == StartRecord {
+                self.boundary = i + 1;
+            }
+        }
+    }
+}
+
+// 在 worker 里解析分块，再用一个续写模式的写出器编码到内存里，返回编码后的内容和写出的记录数
+fn encode_chunk(
+    chunk: &Chunk,
+    skip_header: bool,
+    width: usize,
+    format: OutputFormat,
+    dialect: &CsvDialect,
+    query: &BoundQuery,
+    types: &CsvTypes,
+) -> Result<(Vec<u8>, usize)> {
+    let dialect = CsvDialect {
+        bom: false,
+        ..*dialect
+    };
+    // 列数和表头比较，所以每个分块自己的 reader 不检查列数
+    let mut reader = dialect
+        .reader_builder()
+        .has_headers(skip_header)
+        .flexible(true)
+        .from_reader(chunk.data.as_slice());
+    let mut buf = Vec::new();
+    let mut count = 0;
+    let mut writer = record_writer(&mut buf, format, &dialect)?;
+    writer.resume();
+    writer.write_headers(&query.headers())?;
+    let mut record = StringRecord::new();
+    while reader.read_record(&mut record)? {
+        // 把分块里的位置换算成整个输入里的位置，出错时报告的行号才对
+        let mut pos = record.position().cloned().unwrap_or_else(Position::new);
+        pos.set_line(pos.line() + chunk.line);
+        pos.set_byte(pos.byte() + chunk.byte);
+        if record.len() != width {
+            bail!(
+                "Found record with {} fields at line {}, but the headers have {} fields",
+                record.len(),
+                pos.line(),
+                width
+            );
+        }
+        record.set_position(Some(pos));
+        if !query.matches(&record) {
+            continue;
+        }
+        writer.write(&record_to_value(&record, query, types)?)?;
+        count += 1;
+    }
+    // 不能调用 finish，那样会写出文档的结尾；csv::Writer 在 drop 时会把缓冲区 flush 到 buf 里
+    drop(writer);
+    Ok((buf, count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process_csv_stream;
+
+    // 每行大约 50 字节，这么多行能切出好几个分块
+    const ROWS: usize = CHUNK_BYTES / 12;
+
+    // 超过一个分块的大小，并且包含引号里的换行
+    fn generate(rows: usize) -> String {
+        let mut input = String::from("Name,Position,DOB,Kit Number\n");
+        for i in 0..rows {
+            input.push_str(&format!(
+                "Player {},\"Mid\nfielder\",\"Jan 1, 1990 (30)\",{}\n",
+                i,
+                i % 99
+            ));
+        }
+        input
+    }
+
+    fn convert(
+        input: &str,
+        format: OutputFormat,
+        dialect: &CsvDialect,
+        query: &CsvQuery,
+        jobs: Option<usize>,
+    ) -> Result<Vec<u8>> {
+        let types = CsvTypes {
+            infer: true,
+            ..Default::default()
+        };
+        let mut writer = Vec::new();
+        match jobs {
+            Some(jobs) => process_csv_parallel(
+                &mut input.as_bytes(),
+                &mut writer,
+                format,
+                dialect,
+                &types,
+                query,
+                jobs,
+            )?,
+            None => process_csv_stream(
+                &mut input.as_bytes(),
+                &mut writer,
+                format,
+                dialect,
+                &types,
+                query,
+            )?,
+        }
+        Ok(writer)
+    }
+
+    #[test]
+    fn test_process_csv_parallel_same_as_sequential() -> Result<()> {
+        let input = generate(ROWS);
+        // 第一个分块里没有满足条件的行，测试文档开头的处理
+        let filtered = CsvQuery {
+            filter: Some(format!("Name >= 'Player {}'", ROWS / 2).parse()?),
+            ..Default::default()
+        };
+        let empty = CsvQuery {
+            filter: Some("Name == 'nobody'".parse()?),
+            ..Default::default()
+        };
+        let formats = [
+            OutputFormat::Json,
+            OutputFormat::Yaml,
+            OutputFormat::Toml,
+            OutputFormat::Ndjson,
+            OutputFormat::Xml,
+            OutputFormat::Csv,
+        ];
+        let dialect = CsvDialect::default();
+        for format in formats {
+            for query in [&CsvQuery::default(), &filtered, &empty] {
+                let expected = convert(&input, format, &dialect, query, None)?;
+                // 一个线程时每轮 4 个分块，要分几轮处理
+                for jobs in [1, 3] {
+                    let ret = convert(&input, format, &dialect, query, Some(jobs))?;
+                    assert!(ret == expected, "{} output differs", format);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_parallel_no_header() -> Result<()> {
+        let input = generate(100);
+        let dialect = CsvDialect {
+            has_headers: false,
+            bom: true,
+            ..Default::default()
+        };
+        let query = CsvQuery::default();
+        let expected = convert(&input, OutputFormat::Csv, &dialect, &query, None)?;
+        let ret = convert(&input, OutputFormat::Csv, &dialect, &query, Some(2))?;
+        assert_eq!(ret, expected);
+        assert_eq!(
+            convert("", OutputFormat::Json, &dialect, &query, Some(2))?,
+            b"[]"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_parallel_error() {
+        let mut input = generate(ROWS);
+        input.push_str("Bad,Forward,x,ten\n");
+        let types = CsvTypes {
+            infer: false,
+            schema: serde_yaml::from_str("Kit Number: integer").unwrap(),
+        };
+        let err = process_csv_parallel(
+            &mut input.as_bytes(),
+            &mut Vec::new(),
+            OutputFormat::Json,
+            &CsvDialect::default(),
+            &types,
+            &CsvQuery::default(),
+            2,
+        )
+        .unwrap_err();
+        // 每条记录占两行，加上表头
+        let line = ROWS * 2 + 2;
+        assert_eq!(err.to_string(), format!("Invalid value at line {}", line));
+    }
+
+    #[test]
+    fn test_chunk_splitter_boundary() {
+        let dialect = CsvDialect {
+            escape: Some(b'\\'),
+            comment: Some(b'#'),
+            ..Default::default()
+        };
+        let boundary = |input: &str| {
+            let mut splitter = ChunkSplitter::new(input.as_bytes(), &dialect);
+            splitter.buf = input.as_bytes().to_vec();
+            splitter.scan(0);
+            splitter.boundary
+        };
+        assert_eq!(boundary("a,b\nc,d"), 4);
+        assert_eq!(boundary("a,\"b\nc\""), 0);
+        // 连续的引号和转义的引号都不会结束引号
+        assert_eq!(boundary("a,\"x\"\"\ny\"\n1"), 10);
+        assert_eq!(boundary("a,\"x\\\"\ny\"\n1"), 10);
+        // 不在字段开头的引号是普通字符，注释里的引号也不算
+        assert_eq!(boundary("a,b\"c\nd"), 6);
+        assert_eq!(boundary("#\"\na,b\n1"), 7);
+        assert_eq!(boundary("a,b\r\n\n1"), 6);
+    }
+}
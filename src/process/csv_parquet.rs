@@ -0,0 +1,312 @@
+use super::{value_to_text, RecordWriter};
+use anyhow::{bail, Result};
+use parquet::{
+    basic::{Compression, LogicalType, Repetition, Type as PhysicalType},
+    column::writer::ColumnWriter,
+    data_type::ByteArray,
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    schema::types::Type,
+};
+use serde_json::Value;
+use std::{
+    fs::File,
+    io::{self, Seek, Write},
+    sync::Arc,
+};
+
+// 每个 row group 的行数，也是内存里最多缓存的行数
+const ROW_GROUP_ROWS: usize = 1 << 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ColumnKind {
+    Boolean,
+    Int64,
+    Double,
+    Utf8,
+}
+
+// parquet 的 schema 要在写数据之前确定，而列的类型要看过值才知道，
+// 所以先缓存一个 row group 的行，用第一个 row group 推断出 schema，之后的 row group 都按这个 schema 写
+pub(super) struct ParquetWriter<'a> {
+    writer: &'a mut dyn Write,
+    headers: Vec<String>,
+    rows: Vec<Vec<Value>>,
+    row_group_rows: usize,
+    kinds: Vec<ColumnKind>,
+    // SerializedFileWriter 要求 Send，所以先写到临时文件里，finish 时再复制到输出
+    file: Option<SerializedFileWriter<File>>,
+}
+
+impl<'a> ParquetWriter<'a> {
+    pub(super) fn new(writer: &'a mut dyn Write) -> Self {
+        Self {
+            writer,
+            headers: Vec::new(),
+            rows: Vec::new(),
+            row_group_rows: ROW_GROUP_ROWS,
+            kinds: Vec::new(),
+            file: None,
+        }
+    }
+
+    fn write_row_group(&mut self) -> Result<()> {
+        if self.file.is_none() {
+            self.kinds = (0..self.headers.len())
+                .map(|i| column_kind(self.rows.iter().map(|row| &row[i])))
+                .collect();
+            self.file = Some(self.create_file()?);
+        }
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+
+        let file = self.file.as_mut().expect("file is created above");
+        let mut row_group = file.next_row_group()?;
+        let mut i = 0;
+        while let Some(mut column) = row_group.next_column()? {
+            let values = self.rows.iter().map(|row| &row[i]);
+            write_column(column.untyped(), &self.headers[i], values)?;
+            column.close()?;
+            i += 1;
+        }
+        row_group.close()?;
+        self.rows.clear();
+        Ok(())
+    }
+
+    fn create_file(&self) -> Result<SerializedFileWriter<File>> {
+        // 所有列都是可空的
+        let fields = self
+            .headers
+            .iter()
+            .zip(&self.kinds)
+            .map(|(name, kind)| {
+                let (physical, logical) = match kind {
+                    ColumnKind::Boolean => (PhysicalType::BOOLEAN, None),
+                    ColumnKind::Int64 => (PhysicalType::INT64, None),
+                    ColumnKind::Double => (PhysicalType::DOUBLE, None),
+                    ColumnKind::Utf8 => (PhysicalType::BYTE_ARRAY, Some(LogicalType::String)),
+                };
+                let field = Type::primitive_type_builder(name, physical)
+                    .with_repetition(Repetition::OPTIONAL)
+                    .with_logical_type(logical)
+                    .build()?;
+                Ok(Arc::new(field))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let schema = Type::group_type_builder("schema")
+            .with_fields(fields)
+            .build()?;
+        let props = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        let file =
+            SerializedFileWriter::new(tempfile::tempfile()?, Arc::new(schema), Arc::new(props))?;
+        Ok(file)
+    }
+}
+
+impl RecordWriter for ParquetWriter<'_> {
+    fn write_headers(&mut self, headers: &[String]) -> Result<()> {
+        self.headers = headers.to_vec();
+        Ok(())
+    }
+
+    fn write(&mut self, record: &Value) -> Result<()> {
+        let row = self
+            .headers
+            .iter()
+            .map(|h| record.get(h).cloned().unwrap_or(Value::Null))
+            .collect();
+        self.rows.push(row);
+        if self.rows.len() >= self.row_group_rows {
+            self.write_row_group()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.write_row_group()?;
+        let mut file = self
+            .file
+            .take()
+            .expect("file is created by write_row_group")
+            .into_inner()?;
+        file.rewind()?;
+        io::copy(&mut file, self.writer)?;
+        Ok(())
+    }
+
+    fn write_encoded(&mut self, _encoded: &[u8], _records: usize) -> Result<()> {
+        bail!("parquet output can't be written in parallel")
+    }
+}
+
+// 一列里只有整数就是 INT64，整数和小数混在一起就是 DOUBLE，其他情况都当作字符串
+fn column_kind<'a>(values: impl Iterator<Item = &'a Value>) -> ColumnKind {
+    let mut kind = None;
+    for value in values {
+        let current = match value {
+            Value::Null => continue,
+            Value::Bool(_) => ColumnKind::Boolean,
+            Value::Number(n) if n.is_i64() => ColumnKind::Int64,
+            Value::Number(_) => ColumnKind::Double,
+            _ => ColumnKind::Utf8,
+        };
+        kind = Some(match (kind, current) {
+            (None, current) => current,
+            (Some(k), current) if k == current => k,
+            (Some(ColumnKind::Int64), ColumnKind::Double)
+            | (Some(ColumnKind::Double), ColumnKind::Int64) => ColumnKind::Double,
+            _ => return ColumnKind::Utf8,
+        });
+    }
+    kind.unwrap_or(ColumnKind::Utf8)
+}
+
+// 列的物理类型由第一个 row group 决定，后面的值转换不了时报错，不能悄悄变成 null
+fn write_column<'a>(
+    writer: &mut ColumnWriter,
+    name: &str,
+    values: impl Iterator<Item = &'a Value>,
+) -> Result<()> {
+    match writer {
+        ColumnWriter::BoolColumnWriter(w) => {
+            let (values, levels) = split_nulls(values, name, "boolean", Value::as_bool)?;
+            w.write_batch(&values, Some(&levels), None)?;
+        }
+        ColumnWriter::Int64ColumnWriter(w) => {
+            let (values, levels) = split_nulls(values, name, "int64", Value::as_i64)?;
+            w.write_batch(&values, Some(&levels), None)?;
+        }
+        ColumnWriter::DoubleColumnWriter(w) => {
+            let (values, levels) = split_nulls(values, name, "double", Value::as_f64)?;
+            w.write_batch(&values, Some(&levels), None)?;
+        }
+        ColumnWriter::ByteArrayColumnWriter(w) => {
+            let (values, levels) = split_nulls(values, name, "string", |v| {
+                Some(ByteArray::from(value_to_text(v).into_bytes()))
+            })?;
+            w.write_batch(&values, Some(&levels), None)?;
+        }
+        _ => bail!("Unsupported parquet column type"),
+    }
+    Ok(())
+}
+
+// 可空列的 definition level：1 表示有值，0 表示 null，null 不写进值数组里
+fn split_nulls<'a, T>(
+    values: impl Iterator<Item = &'a Value>,
+    name: &str,
+    kind: &str,
+    f: impl Fn(&Value) -> Option<T>,
+) -> Result<(Vec<T>, Vec<i16>)> {
+    let mut ret = Vec::new();
+    let mut levels = Vec::new();
+    for value in values {
+        if value.is_null() {
+            levels.push(0);
+            continue;
+        }
+        let Some(v) = f(value) else {
+            bail!(
+                "Value {} doesn't fit column {} of type {}, inferred from the first row group",
+                value,
+                name,
+                kind
+            );
+        };
+        ret.push(v);
+        levels.push(1);
+    }
+    Ok((ret, levels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use serde_json::json;
+
+    #[test]
+    fn test_column_kind() {
+        let kind = |values: Value| column_kind(values.as_array().unwrap().iter());
+        assert_eq!(kind(json!([1, null, 2])), ColumnKind::Int64);
+        assert_eq!(kind(json!([1, 2.5])), ColumnKind::Double);
+        assert_eq!(kind(json!([true, null])), ColumnKind::Boolean);
+        assert_eq!(kind(json!([1, "a"])), ColumnKind::Utf8);
+        assert_eq!(kind(json!([null])), ColumnKind::Utf8);
+    }
+
+    #[test]
+    fn test_parquet_writer() -> Result<()> {
+        let mut file = tempfile::tempfile()?;
+        let mut writer = ParquetWriter::new(&mut file);
+        let headers = ["Name", "Kit Number", "Height", "Captain"].map(String::from);
+        writer.write_headers(&headers)?;
+        writer.write(
+            &json!({"Name": "Mattia Perin", "Kit Number": 37, "Height": 1.88, "Captain": false}),
+        )?;
+        writer.write(
+            &json!({"Name": "Moise Kean", "Kit Number": null, "Height": 2, "Captain": true}),
+        )?;
+        writer.finish()?;
+        file.rewind()?;
+
+        let reader = SerializedFileReader::new(file)?;
+        let schema = reader.metadata().file_metadata().schema_descr_ptr();
+        let types: Vec<PhysicalType> = schema.columns().iter().map(|c| c.physical_type()).collect();
+        assert_eq!(
+            types,
+            [
+                PhysicalType::BYTE_ARRAY,
+                PhysicalType::INT64,
+                PhysicalType::DOUBLE,
+                PhysicalType::BOOLEAN
+            ]
+        );
+        let rows = reader
+            .get_row_iter(None)?
+            .map(|row| Ok(row?.to_string()))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            rows,
+            [
+                r#"{Name: "Mattia Perin", Kit Number: 37, Height: 1.88, Captain: false}"#,
+                r#"{Name: "Moise Kean", Kit Number: null, Height: 2.0, Captain: true}"#,
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parquet_writer_row_groups() -> Result<()> {
+        let mut file = tempfile::tempfile()?;
+        let mut writer = ParquetWriter::new(&mut file);
+        writer.row_group_rows = 2;
+        writer.write_headers(&["Name".to_string(), "Kit Number".to_string()])?;
+        for i in 0..5 {
+            writer.write(&json!({"Name": format!("Player {}", i), "Kit Number": i}))?;
+        }
+        writer.finish()?;
+        file.rewind()?;
+
+        let reader = SerializedFileReader::new(file)?;
+        assert_eq!(reader.metadata().num_row_groups(), 3);
+        assert_eq!(reader.get_row_iter(None)?.count(), 5);
+
+        // 后面的 row group 里出现和 schema 不一样的值时报错
+        let mut buf = Vec::new();
+        let mut writer = ParquetWriter::new(&mut buf);
+        writer.row_group_rows = 2;
+        writer.write_headers(&["Kit Number".to_string()])?;
+        writer.write(&json!({"Kit Number": 1}))?;
+        writer.write(&json!({"Kit Number": 2}))?;
+        writer.write(&json!({"Kit Number": "ten"}))?;
+        assert_eq!(
+            writer.finish().unwrap_err().to_string(),
+            "Value \"ten\" doesn't fit column Kit Number of type int64, inferred from the first row group"
+        );
+        Ok(())
+    }
+}
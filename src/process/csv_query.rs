@@ -0,0 +1,468 @@
+use super::csv_stats::parse_number;
+use anyhow::{anyhow, bail, Result};
+use csv::StringRecord;
+use std::{cmp::Ordering, collections::HashMap, iter::Peekable, str::Chars, str::FromStr};
+
+/// Columns to keep, columns to rename and rows to keep, applied while converting
+#[derive(Debug, Default, Clone)]
+pub struct CsvQuery {
+    pub select: Vec<String>,
+    pub rename: Vec<(String, String)>,
+    pub filter: Option<Expr>,
+}
+
+/// A query resolved against the actual headers of a file
+#[derive(Debug)]
+pub struct BoundQuery {
+    // (列的下标, 原来的列名, 输出的列名)
+    columns: Vec<(usize, String, String)>,
+    filter: Option<Expr>,
+}
+
+/// A `--where` expression, e.g. `Position == "Goalkeeper" && `Kit Number` > 10`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Cmp(Operand, CmpOp, Operand),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Column(String),
+    Index(usize),
+    Literal(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    And,
+    Or,
+    Not,
+    Op(CmpOp),
+    Column(String),
+    Literal(String),
+}
+
+impl CsvQuery {
+    /// Resolve column names to positions, fails on columns that don't exist
+    pub fn bind(&self, headers: &StringRecord) -> Result<BoundQuery> {
+        let mut positions: HashMap<&str, usize> = HashMap::new();
+        for (i, h) in headers.iter().enumerate() {
+            // 列名重复时取第一个
+            positions.entry(h).or_insert(i);
+        }
+        let renames: HashMap<&str, &str> = self
+            .rename
+            .iter()
+            .map(|(from, to)| (from.as_str(), to.as_str()))
+            .collect();
+
+        let selected: Vec<(usize, &str)> = if self.select.is_empty() {
+            headers.iter().enumerate().collect()
+        } else {
+            self.select
+                .iter()
+                .map(|name| match positions.get(name.as_str()) {
+                    Some(i) => Ok((*i, name.as_str())),
+                    None => Err(anyhow!("Unknown column: {}", name)),
+                })
+                .collect::<Result<_>>()?
+        };
+        for (from, _) in &self.rename {
+            if !positions.contains_key(from.as_str()) {
+                bail!("Unknown column: {}", from);
+            }
+        }
+
+        let columns = selected
+            .into_iter()
+            .map(|(i, name)| {
+                let output = renames.get(name).copied().unwrap_or(name);
+                (i, name.to_string(), output.to_string())
+            })
+            .collect();
+        let filter = match &self.filter {
+            Some(expr) => Some(expr.bind(&positions)?),
+            None => None,
+        };
+        Ok(BoundQuery { columns, filter })
+    }
+}
+
+impl BoundQuery {
+    /// Names of the output columns, in output order
+    pub fn headers(&self) -> Vec<String> {
+        self.columns.iter().map(|(_, _, h)| h.clone()).collect()
+    }
+
+    /// Output columns as (position in the record, original name, output name)
+    pub fn columns(&self) -> impl Iterator<Item = (usize, &str, &str)> {
+        self.columns
+            .iter()
+            .map(|(i, from, to)| (*i, from.as_str(), to.as_str()))
+    }
+
+    pub fn matches(&self, record: &StringRecord) -> bool {
+        self.filter.as_ref().is_none_or(|expr| expr.eval(record))
+    }
+}
+
+impl Expr {
+    fn bind(&self, positions: &HashMap<&str, usize>) -> Result<Expr> {
+        let expr = match self {
+            Expr::And(l, r) => {
+                Expr::And(Box::new(l.bind(positions)?), Box::new(r.bind(positions)?))
+            }
+            Expr::Or(l, r) => Expr::Or(Box::new(l.bind(positions)?), Box::new(r.bind(positions)?)),
+            Expr::Not(e) => Expr::Not(Box::new(e.bind(positions)?)),
+            Expr::Cmp(l, op, r) => Expr::Cmp(l.bind(positions)?, *op, r.bind(positions)?),
+        };
+        Ok(expr)
+    }
+
+    /// Evaluate a bound expression against a record
+    pub fn eval(&self, record: &StringRecord) -> bool {
+        match self {
+            Expr::And(l, r) => l.eval(record) && r.eval(record),
+            Expr::Or(l, r) => l.eval(record) || r.eval(record),
+            Expr::Not(e) => !e.eval(record),
+            Expr::Cmp(l, op, r) => compare(l.value(record), *op, r.value(record)),
+        }
+    }
+}
+
+impl Operand {
+    fn bind(&self, positions: &HashMap<&str, usize>) -> Result<Operand> {
+        match self {
+            Operand::Column(name) => match positions.get(name.as_str()) {
+                Some(i) => Ok(Operand::Index(*i)),
+                None => bail!("Unknown column: {}", name),
+            },
+            v => Ok(v.clone()),
+        }
+    }
+
+    // 没有 bind 过的列名取不到值，当作空字符串
+    fn value<'a>(&'a self, record: &'a StringRecord) -> &'a str {
+        match self {
+            Operand::Index(i) => record.get(*i).unwrap_or_default(),
+            Operand::Literal(s) => s,
+            Operand::Column(_) => "",
+        }
+    }
+}
+
+// 两边都能解析成有限的数字时按数字比较，否则按字符串比较，
+// 这样 NaN、inf 这些单词还是按字符串比较
+fn compare(left: &str, op: CmpOp, right: &str) -> bool {
+    if op == CmpOp::Contains {
+        return left.contains(right);
+    }
+    let ord = match (parse_number(left), parse_number(right)) {
+        (Some(l), Some(r)) => l.partial_cmp(&r).unwrap_or(Ordering::Equal),
+        _ => left.cmp(right),
+    };
+    match op {
+        CmpOp::Eq => ord == Ordering::Equal,
+        CmpOp::Ne => ord != Ordering::Equal,
+        CmpOp::Lt => ord == Ordering::Less,
+        CmpOp::Le => ord != Ordering::Greater,
+        CmpOp::Gt => ord == Ordering::Greater,
+        CmpOp::Ge => ord != Ordering::Less,
+        CmpOp::Contains => unreachable!(),
+    }
+}
+
+impl FromStr for Expr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.parse_or()?;
+        if parser.pos != parser.tokens.len() {
+            bail!("Unexpected token: {:?}", parser.tokens[parser.pos]);
+        }
+        Ok(expr)
+    }
+}
+
+// 递归下降解析，优先级从低到高：|| -> && -> ! -> 比较
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn parse_or(&mut self) -> Result<Expr> {
+        let mut left = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            left = Expr::Or(Box::new(left), Box::new(self.parse_and()?));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr> {
+        let mut left = self.parse_not()?;
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            left = Expr::And(Box::new(left), Box::new(self.parse_not()?));
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr> {
+        if self.peek() == Some(&Token::Not) {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr> {
+        if self.peek() == Some(&Token::LParen) {
+            self.pos += 1;
+            let expr = self.parse_or()?;
+            if self.next() != Some(Token::RParen) {
+                bail!("Expect )");
+            }
+            return Ok(expr);
+        }
+        let left = self.parse_operand()?;
+        let op = match self.next() {
+            Some(Token::Op(op)) => op,
+            t => bail!("Expect a comparison operator, got: {:?}", t),
+        };
+        let right = self.parse_operand()?;
+        Ok(Expr::Cmp(left, op, right))
+    }
+
+    fn parse_operand(&mut self) -> Result<Operand> {
+        match self.next() {
+            Some(Token::Column(name)) => Ok(Operand::Column(name)),
+            Some(Token::Literal(s)) => Ok(Operand::Literal(s)),
+            t => bail!("Expect a column or a value, got: {:?}", t),
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        let token = match c {
+            c if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            '(' => {
+                chars.next();
+                Token::LParen
+            }
+            ')' => {
+                chars.next();
+                Token::RParen
+            }
+            '"' | '\'' => Token::Literal(read_quoted(&mut chars)?),
+            // 列名里有空格时用反引号括起来，比如 `Kit Number`
+            '`' => Token::Column(read_quoted(&mut chars)?),
+            '=' | '!' | '<' | '>' | '~' | '&' | '|' => read_symbol(&mut chars)?,
+            c if c.is_ascii_digit() || c == '-' || c == '.' => {
+                Token::Literal(read_while(&mut chars, |c| {
+                    c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '+')
+                }))
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let word = read_while(&mut chars, |c| c.is_alphanumeric() || c == '_');
+                match word.to_lowercase().as_str() {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    _ => Token::Column(word),
+                }
+            }
+            c => bail!("Unexpected character: {}", c),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+fn read_quoted(chars: &mut Peekable<Chars>) -> Result<String> {
+    let quote = chars.next().expect("caller peeked a quote");
+    let mut ret = String::new();
+    loop {
+        match chars.next() {
+            Some('\\') => match chars.next() {
+                Some(c) => ret.push(c),
+                None => break,
+            },
+            Some(c) if c == quote => return Ok(ret),
+            Some(c) => ret.push(c),
+            None => break,
+        }
+    }
+    bail!("Unterminated {}", quote)
+}
+
+fn read_while(chars: &mut Peekable<Chars>, f: impl Fn(char) -> bool) -> String {
+    let mut ret = String::new();
+    while let Some(&c) = chars.peek() {
+        if !f(c) {
+            break;
+        }
+        ret.push(c);
+        chars.next();
+    }
+    ret
+}
+
+fn read_symbol(chars: &mut Peekable<Chars>) -> Result<Token> {
+    let first = chars.next().expect("caller peeked a symbol");
+    let second = chars.peek().copied();
+    let (token, consumed) = match (first, second) {
+        ('=', Some('=')) => (Token::Op(CmpOp::Eq), true),
+        ('=', _) => (Token::Op(CmpOp::Eq), false),
+        ('!', Some('=')) => (Token::Op(CmpOp::Ne), true),
+        ('!', _) => (Token::Not, false),
+        ('<', Some('=')) => (Token::Op(CmpOp::Le), true),
+        ('<', _) => (Token::Op(CmpOp::Lt), false),
+        ('>', Some('=')) => (Token::Op(CmpOp::Ge), true),
+        ('>', _) => (Token::Op(CmpOp::Gt), false),
+        ('~', Some('=')) => (Token::Op(CmpOp::Contains), true),
+        ('&', Some('&')) => (Token::And, true),
+        ('|', Some('|')) => (Token::Or, true),
+        (c, _) => bail!("Unexpected character: {}", c),
+    };
+    if consumed {
+        chars.next();
+    }
+    Ok(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(expr: &str) -> Result<bool> {
+        let headers = StringRecord::from(vec!["Name", "Position", "Kit Number"]);
+        let record = StringRecord::from(vec!["Gianluigi Buffon", "Goalkeeper", "77"]);
+        let query = CsvQuery {
+            filter: Some(expr.parse()?),
+            ..Default::default()
+        };
+        Ok(query.bind(&headers)?.matches(&record))
+    }
+
+    #[test]
+    fn test_expr_parse() -> Result<()> {
+        let expr: Expr = "Position == \"Goalkeeper\" && `Kit Number` > 10".parse()?;
+        assert_eq!(
+            expr,
+            Expr::And(
+                Box::new(Expr::Cmp(
+                    Operand::Column("Position".into()),
+                    CmpOp::Eq,
+                    Operand::Literal("Goalkeeper".into())
+                )),
+                Box::new(Expr::Cmp(
+                    Operand::Column("Kit Number".into()),
+                    CmpOp::Gt,
+                    Operand::Literal("10".into())
+                )),
+            )
+        );
+        assert!("Position ==".parse::<Expr>().is_err());
+        assert!("(Position == 'a'".parse::<Expr>().is_err());
+        assert!("Position == 'a".parse::<Expr>().is_err());
+        assert!("Position # 'a'".parse::<Expr>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_expr_eval() -> Result<()> {
+        assert!(eval("Position == \"Goalkeeper\"")?);
+        assert!(eval("Position != 'Defender'")?);
+        // 数字按数值比较，不是按字符串比较
+        assert!(eval("`Kit Number` > 9")?);
+        assert!(eval("`Kit Number` == 77.0")?);
+        assert!(eval("`Kit Number` >= 77 and `Kit Number` <= 77")?);
+        assert!(eval("Name ~= 'Buffon'")?);
+        assert!(eval("not Position == 'Defender' || Name == 'x'")?);
+        assert!(!eval("!(Position == 'Goalkeeper')")?);
+        assert!(!eval(
+            "Position == 'Goalkeeper' && (Name == 'x' or Name == 'y')"
+        )?);
+        assert!(eval("Unknown == 1").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_expr_eval_non_finite() -> Result<()> {
+        let headers = StringRecord::from(vec!["Name", "Code"]);
+        let query = |expr: &str| -> Result<BoundQuery> {
+            CsvQuery {
+                filter: Some(expr.parse()?),
+                ..Default::default()
+            }
+            .bind(&headers)
+        };
+        // NaN、inf 这些不是数字，按字符串比较
+        let nan = StringRecord::from(vec!["Nan", "inf"]);
+        let infinity = StringRecord::from(vec!["Nani", "Infinity"]);
+        assert!(query("Name == \"Nan\"")?.matches(&nan));
+        assert!(!query("Code == \"infinity\"")?.matches(&nan));
+        assert!(!query("Code == \"infinity\"")?.matches(&infinity));
+        assert!(query("Code == \"Infinity\"")?.matches(&infinity));
+        Ok(())
+    }
+
+    #[test]
+    fn test_csv_query_bind() -> Result<()> {
+        let headers = StringRecord::from(vec!["Name", "Position", "Kit Number"]);
+        let query = CsvQuery {
+            select: vec!["Kit Number".into(), "Name".into()],
+            rename: vec![("Kit Number".into(), "kit".into())],
+            filter: None,
+        };
+        let bound = query.bind(&headers)?;
+        assert_eq!(bound.headers(), vec!["kit", "Name"]);
+        assert_eq!(
+            bound.columns().collect::<Vec<_>>(),
+            vec![(2, "Kit Number", "kit"), (0, "Name", "Name")]
+        );
+
+        let query = CsvQuery {
+            select: vec!["Club".into()],
+            ..Default::default()
+        };
+        assert!(query.bind(&headers).is_err());
+        Ok(())
+    }
+}
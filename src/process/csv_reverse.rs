@@ -0,0 +1,533 @@
+use super::{record_writer, value_to_text, CsvDialect, CsvQuery};
+use crate::{cli::OutputFormat, get_reader, get_writer};
+use anyhow::{bail, Context, Result};
+use csv::StringRecord;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use serde_json_path::JsonPath;
+use std::{
+    collections::HashSet,
+    fmt,
+    io::{BufRead, BufReader, Read, Write},
+    str::FromStr,
+};
+
+/// How nested arrays are turned into CSV cells
+#[derive(Debug, Clone)]
+pub struct FlattenOptions {
+    /// Emit one row per element of the array at this flattened path (or `--field` name)
+    /// instead of joining the elements into one cell
+    pub explode: Option<String>,
+    /// Separator used to join arrays of scalars
+    pub separator: String,
+    /// Pick these columns with JSONPath instead of flattening the whole record
+    pub fields: Vec<JsonField>,
+}
+
+/// A column taken from each record with a JSONPath, e.g. `name=$.player.name`
+#[derive(Debug, Clone)]
+pub struct JsonField {
+    pub name: String,
+    pub path: JsonPath,
+}
+
+pub fn process_reverse(
+    input: &str,
+    output: String,
+    from: OutputFormat,
+    format: OutputFormat,
+    dialect: &CsvDialect,
+    flatten: &FlattenOptions,
+    query: &CsvQuery,
+) -> Result<()> {
+    // "-" 表示从 stdin 读、写到 stdout，这样可以放在管道里使用
+    let mut reader = get_reader(input)?;
+    let mut writer = get_writer(&output)?;
+
+    process_reverse_stream(
+        &mut reader,
+        &mut writer,
+        from,
+        format,
+        dialect,
+        flatten,
+        query,
+    )?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Read JSON/YAML/NDJSON/TOML records, flatten them into columns and write them out
+pub fn process_reverse_stream(
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    from: OutputFormat,
+    format: OutputFormat,
+    dialect: &CsvDialect,
+    flatten: &FlattenOptions,
+    query: &CsvQuery,
+) -> Result<()> {
+    // 表头是所有记录字段的并集，必须先把所有记录读完才能确定，所以这里没法流式处理
+    let values = read_values(&mut dialect.decode(reader), from)?;
+    let mut rows = Vec::with_capacity(values.len());
+    for value in &values {
+        if flatten.fields.is_empty() {
+            rows.extend(flatten_value("", value, flatten));
+        } else {
+            rows.extend(project_value(value, flatten));
+        }
+    }
+    // 指定了 --field 时，列就是这些字段，顺序和参数的顺序一致
+    let headers = if flatten.fields.is_empty() {
+        union_headers(&rows)
+    } else {
+        flatten.fields.iter().map(|f| f.name.clone()).collect()
+    };
+    let bound = query.bind(&StringRecord::from(headers.clone()))?;
+
+    let mut writer = record_writer(writer, format, dialect)?;
+    writer.write_headers(&bound.headers())?;
+    for row in rows {
+        // --where 是按 csv 的单元格求值的，所以先按表头把这一行转成 StringRecord
+        if query.filter.is_some() {
+            let record: StringRecord = headers
+                .iter()
+                .map(|h| row.get(h).map(value_to_text).unwrap_or_default())
+                .collect();
+            if !bound.matches(&record) {
+                continue;
+            }
+        }
+        let row: Map<String, Value> = bound
+            .columns()
+            .filter_map(|(_, name, output)| Some((output.to_string(), row.get(name)?.clone())))
+            .collect();
+        writer.write(&Value::Object(row))?;
+    }
+    writer.finish()
+}
+
+fn read_values(reader: &mut dyn Read, from: OutputFormat) -> Result<Vec<Value>> {
+    let values = match from {
+        OutputFormat::Json => match serde_json::from_reader(reader)? {
+            Value::Array(values) => values,
+            value => vec![value],
+        },
+        OutputFormat::Ndjson => {
+            let mut values = Vec::new();
+            for (i, line) in BufReader::new(reader).lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let value = serde_json::from_str(&line)
+                    .with_context(|| format!("Invalid JSON at line {}", i + 1))?;
+                values.push(value);
+            }
+            values
+        }
+        OutputFormat::Yaml => {
+            // 一个 yaml 文件里可能有多个 --- 分隔的文档
+            let mut values = Vec::new();
+            for doc in serde_yaml::Deserializer::from_reader(reader) {
+                match Value::deserialize(doc)? {
+                    Value::Array(v) => values.extend(v),
+                    Value::Null => {}
+                    v => values.push(v),
+                }
+            }
+            values
+        }
+        OutputFormat::Toml => {
+            // rcli 输出的 toml 是 [[row]] 这样的数组表，读回来时把它展开
+            let mut content = String::new();
+            reader.read_to_string(&mut content)?;
+            let mut value: Value = toml::from_str(&content)?;
+            let rows = match &mut value {
+                Value::Object(map) if map.len() == 1 => map
+                    .values_mut()
+                    .next()
+                    .and_then(Value::as_array_mut)
+                    .map(std::mem::take),
+                _ => None,
+            };
+            rows.unwrap_or_else(|| vec![value])
+        }
+        OutputFormat::Csv
+        | OutputFormat::Xml
+        | OutputFormat::Xlsx
+        | OutputFormat::Parquet
+        | OutputFormat::Table => {
+            anyhow::bail!("Unsupported input format: {}", from)
+        }
+    };
+    Ok(values)
+}
+
+// 把一条嵌套的记录拍平成若干行：对象的 key 用 . 连接，--explode 指定的数组展开成多行，
+// 其他数组合并成一个单元格；只展开一个数组，不会出现多个数组的笛卡尔积
+fn flatten_value(prefix: &str, value: &Value, opts: &FlattenOptions) -> Vec<Map<String, Value>> {
+    match value {
+        Value::Object(map) => {
+            let mut rows = vec![Map::new()];
+            for (k, v) in map {
+                let key = join_key(prefix, k);
+                rows = cartesian(rows, flatten_value(&key, v, opts));
+            }
+            rows
+        }
+        Value::Array(items) if items.is_empty() => vec![single(prefix, Value::Null)],
+        Value::Array(items) if opts.explodes(prefix) => items
+            .iter()
+            .flat_map(|item| flatten_value(prefix, item, opts))
+            .collect(),
+        Value::Array(items) if items.iter().all(is_scalar) => {
+            let joined = items
+                .iter()
+                .map(value_to_text)
+                .collect::<Vec<_>>()
+                .join(&opts.separator);
+            vec![single(prefix, Value::String(joined))]
+        }
+        // 数组里有对象或者数组，没法合并成一个单元格，用下标作为 key 继续拍平
+        Value::Array(items) => {
+            let mut rows = vec![Map::new()];
+            for (i, item) in items.iter().enumerate() {
+                let key = join_key(prefix, &i.to_string());
+                rows = cartesian(rows, flatten_value(&key, item, opts));
+            }
+            rows
+        }
+        v => vec![single(prefix, v.clone())],
+    }
+}
+
+// 每个 --field 取出一列：没有匹配时为 null，匹配到一个时原样保留（yaml/json 输出时对象不会被拍平），
+// 匹配到多个时和数组的处理一样，合并成一个单元格，或者这一列是 --explode 指定的列时展开成多行
+fn project_value(value: &Value, opts: &FlattenOptions) -> Vec<Map<String, Value>> {
+    let mut rows = vec![Map::new()];
+    for field in &opts.fields {
+        let nodes = field.path.query(value).all();
+        let values = match nodes.as_slice() {
+            [] => vec![Value::Null],
+            [v] => vec![(*v).clone()],
+            nodes if opts.explodes(&field.name) => nodes.iter().map(|v| (*v).clone()).collect(),
+            nodes if nodes.iter().all(|v| is_scalar(v)) => {
+                let joined = nodes
+                    .iter()
+                    .map(|v| value_to_text(v))
+                    .collect::<Vec<_>>()
+                    .join(&opts.separator);
+                vec![Value::String(joined)]
+            }
+            nodes => vec![Value::Array(nodes.iter().map(|v| (*v).clone()).collect())],
+        };
+        let columns = values.into_iter().map(|v| single(&field.name, v)).collect();
+        rows = cartesian(rows, columns);
+    }
+    rows
+}
+
+fn join_key(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", prefix, key)
+    }
+}
+
+// 顶层直接是标量的话，用 value 作为列名
+fn single(prefix: &str, value: Value) -> Map<String, Value> {
+    let key = if prefix.is_empty() { "value" } else { prefix };
+    let mut map = Map::new();
+    map.insert(key.to_string(), value);
+    map
+}
+
+fn is_scalar(value: &Value) -> bool {
+    !matches!(value, Value::Object(_) | Value::Array(_))
+}
+
+fn cartesian(
+    left: Vec<Map<String, Value>>,
+    right: Vec<Map<String, Value>>,
+) -> Vec<Map<String, Value>> {
+    if right.is_empty() {
+        return left;
+    }
+    let mut rows = Vec::with_capacity(left.len() * right.len());
+    for l in &left {
+        for r in &right {
+            let mut row = l.clone();
+            row.extend(r.clone());
+            rows.push(row);
+        }
+    }
+    rows
+}
+
+// 按第一次出现的顺序合并所有行的列名
+fn union_headers(rows: &[Map<String, Value>]) -> Vec<String> {
+    let mut seen = HashSet::new();
+    let mut headers = Vec::new();
+    for row in rows {
+        for k in row.keys() {
+            if seen.insert(k.as_str()) {
+                headers.push(k.clone());
+            }
+        }
+    }
+    headers
+}
+
+impl FlattenOptions {
+    fn explodes(&self, path: &str) -> bool {
+        self.explode.as_deref() == Some(path)
+    }
+}
+
+impl Default for FlattenOptions {
+    fn default() -> Self {
+        Self {
+            explode: None,
+            separator: ";".to_string(),
+            fields: Vec::new(),
+        }
+    }
+}
+
+impl FromStr for JsonField {
+    type Err = anyhow::Error;
+
+    // 列名里不能有 =，JSONPath 里的 == 不受影响
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((name, path)) = s.split_once('=') else {
+            bail!("Expect name=$.path, got: {}", s);
+        };
+        let name = name.trim();
+        if name.is_empty() {
+            bail!("Expect name=$.path, got: {}", s);
+        }
+        let path = JsonPath::parse(path.trim())
+            .with_context(|| format!("Invalid JSONPath for field {}", name))?;
+        Ok(Self {
+            name: name.to_string(),
+            path,
+        })
+    }
+}
+
+impl fmt::Display for JsonField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{process_csv_stream, CsvTypes};
+    use serde_json::json;
+
+    fn reverse(input: &str, from: OutputFormat, opts: &FlattenOptions) -> Result<String> {
+        let mut writer = Vec::new();
+        process_reverse_stream(
+            &mut input.as_bytes(),
+            &mut writer,
+            from,
+            OutputFormat::Csv,
+            &CsvDialect::default(),
+            opts,
+            &CsvQuery::default(),
+        )?;
+        Ok(String::from_utf8(writer)?)
+    }
+
+    #[test]
+    fn test_flatten_value_join() {
+        let value =
+            json!({"name": "A", "club": {"name": "Juventus", "city": "Turin"}, "tags": ["x", 1]});
+        let rows = flatten_value("", &value, &FlattenOptions::default());
+        assert_eq!(
+            rows,
+            vec![
+                json!({"club.city": "Turin", "club.name": "Juventus", "name": "A", "tags": "x;1"})
+                    .as_object()
+                    .unwrap()
+                    .clone()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_flatten_value_nested_array() {
+        let value = json!({"items": [{"id": 1}, {"id": 2}]});
+        let rows = flatten_value("", &value, &FlattenOptions::default());
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["items.0.id"], json!(1));
+        assert_eq!(rows[0]["items.1.id"], json!(2));
+    }
+
+    #[test]
+    fn test_flatten_value_explode() {
+        let opts = FlattenOptions {
+            explode: Some("tags".to_string()),
+            ..Default::default()
+        };
+        // 两个数组时只展开指定的那个，另一个合并到单元格里
+        let value = json!({"name": "A", "tags": ["x", "y"], "ids": [1, 2], "empty": []});
+        let rows = flatten_value("", &value, &opts);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["tags"], json!("x"));
+        assert_eq!(rows[1]["tags"], json!("y"));
+        assert!(rows
+            .iter()
+            .all(|r| r["name"] == json!("A") && r["ids"] == json!("1;2") && r["empty"].is_null()));
+
+        // 嵌套的路径，展开的元素是对象时继续拍平
+        let opts = FlattenOptions {
+            explode: Some("club.players".to_string()),
+            ..Default::default()
+        };
+        let value = json!({"club": {"name": "Juventus", "players": [{"id": 1}, {"id": 2}]}});
+        let rows = flatten_value("", &value, &opts);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1]["club.players.id"], json!(2));
+        assert_eq!(rows[1]["club.name"], json!("Juventus"));
+    }
+
+    #[test]
+    fn test_process_reverse_json() -> Result<()> {
+        let input = r#"[{"a": 1, "b": {"c": true}}, {"a": 2, "d": null}]"#;
+        assert_eq!(
+            reverse(input, OutputFormat::Json, &FlattenOptions::default())?,
+            "a,b.c,d\n1,true,\n2,,\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_reverse_ndjson_yaml() -> Result<()> {
+        let opts = FlattenOptions::default();
+        let expected = "a,b\n1,x\n2,y\n";
+        let ndjson = "{\"a\":1,\"b\":\"x\"}\n\n{\"a\":2,\"b\":\"y\"}\n";
+        assert_eq!(reverse(ndjson, OutputFormat::Ndjson, &opts)?, expected);
+        let yaml = "- a: 1\n  b: x\n---\na: 2\nb: y\n";
+        assert_eq!(reverse(yaml, OutputFormat::Yaml, &opts)?, expected);
+        assert!(reverse("a,b", OutputFormat::Xml, &opts).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_reverse_query() -> Result<()> {
+        let input = r#"[{"name": "A", "club": {"city": "Turin"}}, {"name": "B", "club": {"city": "Milan"}}]"#;
+        let query = CsvQuery {
+            select: vec!["name".into(), "club.city".into()],
+            rename: vec![("club.city".into(), "city".into())],
+            filter: Some("`club.city` == 'Milan'".parse()?),
+        };
+        let mut writer = Vec::new();
+        process_reverse_stream(
+            &mut input.as_bytes(),
+            &mut writer,
+            OutputFormat::Json,
+            OutputFormat::Csv,
+            &CsvDialect::default(),
+            &FlattenOptions::default(),
+            &query,
+        )?;
+        assert_eq!(String::from_utf8(writer)?, "name,city\nB,Milan\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_reverse_fields() -> Result<()> {
+        let input = r#"{"player": {"name": "Mattia Perin", "number": 37}, "tags": ["gk", "italy"]}
+{"player": {"name": "Moise Kean"}, "tags": []}
+"#;
+        let fields = [
+            "number=$.player.number",
+            "name=$.player.name",
+            "tags=$.tags[*]",
+        ]
+        .iter()
+        .map(|f| f.parse())
+        .collect::<Result<Vec<JsonField>>>()?;
+        let opts = FlattenOptions {
+            fields,
+            ..Default::default()
+        };
+        assert_eq!(
+            reverse(input, OutputFormat::Ndjson, &opts)?,
+            "number,name,tags\n37,Mattia Perin,gk;italy\n,Moise Kean,\n"
+        );
+
+        let opts = FlattenOptions {
+            explode: Some("tags".to_string()),
+            ..opts
+        };
+        assert_eq!(
+            reverse(input, OutputFormat::Ndjson, &opts)?,
+            "number,name,tags\n37,Mattia Perin,gk\n37,Mattia Perin,italy\n,Moise Kean,\n"
+        );
+
+        // 匹配到的对象在 yaml 输出里保持原来的结构
+        let opts = FlattenOptions {
+            fields: vec!["player=$.player".parse()?],
+            ..Default::default()
+        };
+        let mut writer = Vec::new();
+        process_reverse_stream(
+            &mut input.as_bytes(),
+            &mut writer,
+            OutputFormat::Ndjson,
+            OutputFormat::Yaml,
+            &CsvDialect::default(),
+            &opts,
+            &CsvQuery::default(),
+        )?;
+        assert_eq!(
+            String::from_utf8(writer)?,
+            "- player:\n    name: Mattia Perin\n    number: 37\n- player:\n    name: Moise Kean\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_json_field() -> Result<()> {
+        let field: JsonField = "name = $.player.name".parse()?;
+        assert_eq!(field.name, "name");
+        assert_eq!(field.to_string(), "name=$.player.name");
+        assert!("$.player.name".parse::<JsonField>().is_err());
+        assert!("=$.a".parse::<JsonField>().is_err());
+        assert!("name=player.name".parse::<JsonField>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_reverse_round_trip() -> Result<()> {
+        let input = "Name,Kit Number\nA,1\nB,2\n";
+        let dialect = CsvDialect::default();
+        let types = CsvTypes::default();
+        for format in [OutputFormat::Json, OutputFormat::Yaml, OutputFormat::Toml] {
+            let mut converted = Vec::new();
+            process_csv_stream(
+                &mut input.as_bytes(),
+                &mut converted,
+                format,
+                &dialect,
+                &types,
+                &CsvQuery::default(),
+            )?;
+            let ret = reverse(
+                std::str::from_utf8(&converted)?,
+                format,
+                &FlattenOptions::default(),
+            )?;
+            // json 的 key 是排序的，所以列的顺序会变
+            assert_eq!(ret, "Kit Number,Name\n1,A\n2,B\n");
+        }
+        Ok(())
+    }
+}
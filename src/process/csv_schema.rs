@@ -0,0 +1,198 @@
+use super::load_config;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{collections::HashMap, fmt, path::Path};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColumnType {
+    #[serde(alias = "str")]
+    String,
+    #[serde(alias = "int")]
+    Integer,
+    #[serde(alias = "number")]
+    Float,
+    #[serde(alias = "bool")]
+    Boolean,
+}
+
+/// Column types pinned by the user, e.g. a YAML file with `Kit Number: integer`
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct CsvSchema {
+    columns: HashMap<String, ColumnType>,
+}
+
+/// How raw CSV cells are turned into values: all strings (the default), inferred, or pinned by a schema
+#[derive(Debug, Default, Clone)]
+pub struct CsvTypes {
+    pub infer: bool,
+    pub schema: CsvSchema,
+}
+
+impl CsvSchema {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        load_config(path)
+    }
+
+    pub fn get(&self, column: &str) -> Option<ColumnType> {
+        self.columns.get(column).copied()
+    }
+}
+
+impl CsvTypes {
+    /// Convert a single cell of the given column into a JSON value
+    pub fn parse(&self, column: &str, cell: &str) -> Result<Value> {
+        match self.schema.get(column) {
+            Some(ty) => parse_typed(cell, ty)
+                .with_context(|| format!("column {:?}: {:?} is not a valid {}", column, cell, ty)),
+            None if self.infer => Ok(infer_value(cell)),
+            None => Ok(Value::String(cell.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for ColumnType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ColumnType::String => "string",
+            ColumnType::Integer => "integer",
+            ColumnType::Float => "float",
+            ColumnType::Boolean => "boolean",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+pub(crate) fn parse_typed(cell: &str, ty: ColumnType) -> Result<Value> {
+    // 字符串类型原样保留，空字符串也不转成 null
+    if ty == ColumnType::String {
+        return Ok(Value::String(cell.to_string()));
+    }
+    let cell = cell.trim();
+    if cell.is_empty() {
+        return Ok(Value::Null);
+    }
+    let value = match ty {
+        ColumnType::String => Value::String(cell.to_string()),
+        ColumnType::Integer => cell.parse::<i64>()?.into(),
+        ColumnType::Float => parse_float(cell).context("invalid float")?,
+        ColumnType::Boolean => parse_bool(cell).context("invalid boolean")?.into(),
+    };
+    Ok(value)
+}
+
+// 推断顺序：空 -> null，整数 -> 浮点数 -> 布尔 -> 字符串
+// 日期之类的保持原样，交给下游自己处理
+fn infer_value(cell: &str) -> Value {
+    let trimmed = cell.trim();
+    if trimmed.is_empty() {
+        return Value::Null;
+    }
+    if !has_leading_zero(trimmed) {
+        if let Ok(v) = trimmed.parse::<i64>() {
+            return v.into();
+        }
+        if let Some(v) = parse_float(trimmed) {
+            return v;
+        }
+    }
+    if let Some(v) = parse_bool(trimmed) {
+        return v.into();
+    }
+    Value::String(cell.to_string())
+}
+
+// 007 这种带前导 0 的一般是编号，不当作数字，否则会丢掉前面的 0
+fn has_leading_zero(s: &str) -> bool {
+    let digits = s.strip_prefix('-').unwrap_or(s).as_bytes();
+    digits.len() > 1 && digits[0] == b'0' && digits[1].is_ascii_digit()
+}
+
+fn parse_float(s: &str) -> Option<Value> {
+    // 排除 inf、NaN 这种 Rust 能解析但 JSON 表示不了的值
+    if !s.bytes().any(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let v = s.parse::<f64>().ok()?;
+    serde_json::Number::from_f64(v).map(Value::Number)
+}
+
+fn parse_bool(s: &str) -> Option<bool> {
+    if s.eq_ignore_ascii_case("true") {
+        Some(true)
+    } else if s.eq_ignore_ascii_case("false") {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_infer_value() {
+        assert_eq!(infer_value("10"), json!(10));
+        assert_eq!(infer_value("-3"), json!(-3));
+        assert_eq!(infer_value("1.5"), json!(1.5));
+        assert_eq!(infer_value("1e3"), json!(1000.0));
+        assert_eq!(infer_value("TRUE"), json!(true));
+        assert_eq!(infer_value("false"), json!(false));
+        assert_eq!(infer_value(""), Value::Null);
+        assert_eq!(infer_value("  "), Value::Null);
+        assert_eq!(infer_value("0"), json!(0));
+        assert_eq!(infer_value("0.5"), json!(0.5));
+        assert_eq!(infer_value("007"), json!("007"));
+        assert_eq!(infer_value("+10"), json!(10));
+        assert_eq!(infer_value("NaN"), json!("NaN"));
+        assert_eq!(infer_value("inf"), json!("inf"));
+        assert_eq!(infer_value("1990-04-18"), json!("1990-04-18"));
+        assert_eq!(infer_value("Apr 18, 1990 (29)"), json!("Apr 18, 1990 (29)"));
+    }
+
+    #[test]
+    fn test_csv_types_parse() -> Result<()> {
+        let schema: CsvSchema = serde_yaml::from_str("Kit Number: integer\nDOB: str\nok: bool")?;
+        let types = CsvTypes {
+            infer: false,
+            schema,
+        };
+        assert_eq!(types.parse("Kit Number", "10")?, json!(10));
+        assert_eq!(types.parse("Kit Number", "")?, Value::Null);
+        assert_eq!(types.parse("DOB", "")?, json!(""));
+        assert_eq!(types.parse("ok", "False")?, json!(false));
+        // 没有指定类型，也没开推断，保持字符串
+        assert_eq!(types.parse("Name", "10")?, json!("10"));
+
+        let err = types.parse("Kit Number", "ten").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "column \"Kit Number\": \"ten\" is not a valid integer"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_csv_schema_pins_over_inference() -> Result<()> {
+        let types = CsvTypes {
+            infer: true,
+            schema: serde_yaml::from_str("zip: string")?,
+        };
+        assert_eq!(types.parse("zip", "10115")?, json!("10115"));
+        assert_eq!(types.parse("Kit Number", "10")?, json!(10));
+        Ok(())
+    }
+
+    #[test]
+    fn test_csv_schema_load() -> Result<()> {
+        let schema = CsvSchema::load("fixtures/juventus.schema.yaml")?;
+        assert_eq!(schema.get("Kit Number"), Some(ColumnType::Integer));
+        assert_eq!(schema.get("Name"), Some(ColumnType::String));
+        assert_eq!(schema.get("Unknown"), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,546 @@
+use super::{
+    csv_join::{positions, read_headers, to_map},
+    csv_stats::parse_number,
+    record_writer, CsvDialect,
+};
+use crate::cli::OutputFormat;
+use anyhow::{bail, Result};
+use csv::StringRecord;
+use serde_json::Value;
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    fmt,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Seek, Write},
+    str::FromStr,
+};
+
+/// A column to sort by, e.g. `Kit Number:num:desc`, strings in ascending order by default
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortKey {
+    pub column: String,
+    pub numeric: bool,
+    pub descending: bool,
+}
+
+// 绑定到列号之后的排序键
+#[derive(Debug, Clone, Copy)]
+struct SortColumn {
+    index: usize,
+    numeric: bool,
+    descending: bool,
+}
+
+// 最多同时打开的 run 数（包括合并时写出的那个），打开的文件数限制（ulimit -n）很小时也能排序
+const MAX_FAN_IN: usize = 8;
+
+// 外部排序：内存里的行超过 buffer_size 字节就排好序写到临时文件里（一个 run），
+// 最后把所有 run 做 k 路归并，这样内存占用只和 buffer_size 有关，和文件大小无关，
+// 打开的临时文件也不会超过 MAX_FAN_IN 个
+struct ExternalSorter<'a> {
+    keys: &'a [SortColumn],
+    buffer_size: usize,
+    buffer: Vec<StringRecord>,
+    used: usize,
+    runs: Vec<File>,
+}
+
+// 归并时每个 run 当前最小的一行，run 是它来自第几个 run
+struct Head<'a> {
+    record: StringRecord,
+    run: usize,
+    keys: &'a [SortColumn],
+}
+
+struct MergeRuns<'a> {
+    readers: Vec<csv::Reader<BufReader<File>>>,
+    heap: BinaryHeap<Head<'a>>,
+    keys: &'a [SortColumn],
+}
+
+/// Sort CSV rows by the given keys, rows that compare equal keep their original order.
+/// Inputs larger than `buffer_size` bytes are sorted in runs spilled to temp files.
+pub fn process_csv_sort(
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    format: OutputFormat,
+    dialect: &CsvDialect,
+    by: &[SortKey],
+    buffer_size: usize,
+) -> Result<()> {
+    let mut reader = dialect.reader_builder().from_reader(dialect.decode(reader));
+    let (headers, first) = read_headers(&mut reader, dialect)?;
+    let columns: Vec<String> = by.iter().map(|k| k.column.clone()).collect();
+    let keys: Vec<SortColumn> = positions(&headers, &columns)?
+        .into_iter()
+        .zip(by)
+        .map(|(index, key)| SortColumn {
+            index,
+            numeric: key.numeric,
+            descending: key.descending,
+        })
+        .collect();
+
+    let mut sorter = ExternalSorter::new(&keys, buffer_size);
+    for record in first.into_iter().map(Ok).chain(reader.records()) {
+        sorter.push(record?)?;
+    }
+
+    let mut writer = record_writer(writer, format, dialect)?;
+    writer.write_headers(&headers.iter().map(String::from).collect::<Vec<_>>())?;
+    for record in sorter.finish()? {
+        writer.write(&Value::Object(to_map(&headers, &record?)))?;
+    }
+    writer.finish()
+}
+
+/// Drop rows whose `by` columns (the whole row if empty) were already seen, keeping the
+/// first occurrence and the original order. Uses the same external sort as `process_csv_sort`.
+pub fn process_csv_dedupe(
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    format: OutputFormat,
+    dialect: &CsvDialect,
+    by: &[String],
+    buffer_size: usize,
+) -> Result<()> {
+    let mut reader = dialect.reader_builder().from_reader(dialect.decode(reader));
+    let (headers, first) = read_headers(&mut reader, dialect)?;
+    let columns = if by.is_empty() {
+        (0..headers.len()).collect()
+    } else {
+        positions(&headers, by)?
+    };
+
+    // 第一遍：每行前面加上行号，按 key 排序后相同的行会挨在一起，
+    // 排序是稳定的，所以每组的第一行就是最先出现的那一行
+    let by_key: Vec<SortColumn> = columns
+        .iter()
+        .map(|i| SortColumn {
+            index: i + 1,
+            numeric: false,
+            descending: false,
+        })
+        .collect();
+    let mut sorter = ExternalSorter::new(&by_key, buffer_size);
+    for (seq, record) in first
+        .into_iter()
+        .map(Ok)
+        .chain(reader.records())
+        .enumerate()
+    {
+        let record = record?;
+        let mut tagged =
+            StringRecord::with_capacity(record.as_slice().len() + 20, record.len() + 1);
+        tagged.push_field(&seq.to_string());
+        tagged.extend(record.iter());
+        sorter.push(tagged)?;
+    }
+
+    // 第二遍：跳过重复的行，剩下的再按行号排回原来的顺序
+    let by_seq = [SortColumn {
+        index: 0,
+        numeric: true,
+        descending: false,
+    }];
+    let mut restore = ExternalSorter::new(&by_seq, buffer_size);
+    let mut last: Option<StringRecord> = None;
+    for record in sorter.finish()? {
+        let record = record?;
+        if let Some(last) = &last {
+            if compare(&by_key, last, &record).is_eq() {
+                continue;
+            }
+        }
+        restore.push(record.clone())?;
+        last = Some(record);
+    }
+
+    let mut writer = record_writer(writer, format, dialect)?;
+    writer.write_headers(&headers.iter().map(String::from).collect::<Vec<_>>())?;
+    for record in restore.finish()? {
+        let record: StringRecord = record?.iter().skip(1).collect();
+        writer.write(&Value::Object(to_map(&headers, &record)))?;
+    }
+    writer.finish()
+}
+
+impl<'a> ExternalSorter<'a> {
+    fn new(keys: &'a [SortColumn], buffer_size: usize) -> Self {
+        Self {
+            keys,
+            buffer_size,
+            buffer: Vec::new(),
+            used: 0,
+            runs: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, record: StringRecord) -> Result<()> {
+        // 估算一行占用的内存：字段内容加上每个字段的边界
+        self.used += record.as_slice().len()
+            + record.len() * std::mem::size_of::<usize>()
+            + std::mem::size_of::<StringRecord>();
+        self.buffer.push(record);
+        if self.used >= self.buffer_size {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    fn sort_buffer(&mut self) {
+        let keys = self.keys;
+        self.buffer.sort_by(|a, b| compare(keys, a, b));
+    }
+
+    // 匿名临时文件在关闭后由系统删除，位置可以用 TMPDIR 环境变量指定
+    fn spill(&mut self) -> Result<()> {
+        self.sort_buffer();
+        let file = write_run(self.buffer.drain(..).map(Ok))?;
+        self.runs.push(file);
+        self.used = 0;
+        // 已有的 run 按原来的顺序合并成一个，后面的 run 都排在它后面，所以排序仍然是稳定的
+        if self.runs.len() + 1 >= MAX_FAN_IN {
+            let runs = std::mem::take(&mut self.runs);
+            let merged = write_run(MergeRuns::new(runs, self.keys)?)?;
+            self.runs.push(merged);
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Box<dyn Iterator<Item = Result<StringRecord>> + 'a>> {
+        // 没有溢出到文件时直接在内存里排序
+        if self.runs.is_empty() {
+            self.sort_buffer();
+            return Ok(Box::new(self.buffer.into_iter().map(Ok)));
+        }
+        if !self.buffer.is_empty() {
+            self.spill()?;
+        }
+        Ok(Box::new(MergeRuns::new(self.runs, self.keys)?))
+    }
+}
+
+fn write_run(records: impl Iterator<Item = Result<StringRecord>>) -> Result<File> {
+    let mut writer = csv::WriterBuilder::new()
+        .flexible(true)
+        .from_writer(BufWriter::new(tempfile::tempfile()?));
+    for record in records {
+        writer.write_record(&record?)?;
+    }
+    let file = writer
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .into_inner()
+        .map_err(|e| e.into_error())?;
+    Ok(file)
+}
+
+impl<'a> MergeRuns<'a> {
+    fn new(runs: Vec<File>, keys: &'a [SortColumn]) -> Result<Self> {
+        let mut merge = Self {
+            readers: Vec::with_capacity(runs.len()),
+            heap: BinaryHeap::with_capacity(runs.len()),
+            keys,
+        };
+        for (run, mut file) in runs.into_iter().enumerate() {
+            file.rewind()?;
+            merge.readers.push(
+                csv::ReaderBuilder::new()
+                    .has_headers(false)
+                    .flexible(true)
+                    .from_reader(BufReader::new(file)),
+            );
+            merge.advance(run)?;
+        }
+        Ok(merge)
+    }
+
+    // 从第 run 个文件里再读一行放进堆里
+    fn advance(&mut self, run: usize) -> Result<()> {
+        let mut record = StringRecord::new();
+        if self.readers[run].read_record(&mut record)? {
+            self.heap.push(Head {
+                record,
+                run,
+                keys: self.keys,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for MergeRuns<'_> {
+    type Item = Result<StringRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let head = self.heap.pop()?;
+        if let Err(e) = self.advance(head.run) {
+            return Some(Err(e));
+        }
+        Some(Ok(head.record))
+    }
+}
+
+// BinaryHeap 是大顶堆，所以比较结果要反过来；相等时先出前面的 run，保证排序稳定
+impl Ord for Head<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare(self.keys, &self.record, &other.record)
+            .then(self.run.cmp(&other.run))
+            .reverse()
+    }
+}
+
+impl PartialOrd for Head<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Head<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Head<'_> {}
+
+fn compare(keys: &[SortColumn], a: &StringRecord, b: &StringRecord) -> Ordering {
+    keys.iter()
+        .map(|key| key.compare(a, b))
+        .find(|o| o.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+impl SortColumn {
+    fn compare(&self, a: &StringRecord, b: &StringRecord) -> Ordering {
+        let a = a.get(self.index).unwrap_or_default();
+        let b = b.get(self.index).unwrap_or_default();
+        let ordering = if self.numeric {
+            // 不是数字的值（比如空值、NaN、inf）不管升序降序都排在数字后面，它们之间按字符串比较
+            match (parse_number(a), parse_number(b)) {
+                (Some(x), Some(y)) => x.total_cmp(&y),
+                (Some(_), None) => return Ordering::Less,
+                (None, Some(_)) => return Ordering::Greater,
+                (None, None) => a.cmp(b),
+            }
+        } else {
+            a.cmp(b)
+        };
+        if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+}
+
+impl FromStr for SortKey {
+    type Err = anyhow::Error;
+
+    // 列名本身可能带冒号，所以从后往前识别类型和顺序
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut column = s;
+        let mut numeric = None;
+        let mut descending = None;
+        while let Some((rest, option)) = column.rsplit_once(':') {
+            match option.trim().to_lowercase().as_str() {
+                "num" | "number" if numeric.is_none() => numeric = Some(true),
+                "str" | "string" if numeric.is_none() => numeric = Some(false),
+                "asc" if descending.is_none() => descending = Some(false),
+                "desc" if descending.is_none() => descending = Some(true),
+                _ => break,
+            }
+            column = rest;
+        }
+        if column.is_empty() {
+            bail!("Sort key needs a column, e.g. Kit Number:num:desc");
+        }
+        Ok(Self {
+            column: column.to_string(),
+            numeric: numeric.unwrap_or_default(),
+            descending: descending.unwrap_or_default(),
+        })
+    }
+}
+
+impl fmt::Display for SortKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = if self.numeric { "num" } else { "str" };
+        let order = if self.descending { "desc" } else { "asc" };
+        write!(f, "{}:{}:{}", self.column, kind, order)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &str = "Name,Position,Kit Number\n\
+        Wojciech Szczesny,Goalkeeper,1\n\
+        Mattia Perin,Goalkeeper,37\n\
+        Leonardo Bonucci,Centre-Back,19\n\
+        Mario Mandzukic,Centre-Forward,17\n\
+        Moise Kean,Centre-Forward,\n\
+        Carlo Pinsoglio,Goalkeeper,31\n";
+
+    fn sort(input: &str, by: &str, buffer_size: usize) -> Result<String> {
+        let by = by
+            .split(',')
+            .map(|k| k.parse())
+            .collect::<Result<Vec<SortKey>>>()?;
+        let mut writer = Vec::new();
+        process_csv_sort(
+            &mut input.as_bytes(),
+            &mut writer,
+            OutputFormat::Csv,
+            &CsvDialect::default(),
+            &by,
+            buffer_size,
+        )?;
+        Ok(String::from_utf8(writer)?)
+    }
+
+    fn dedupe(input: &str, by: &[&str], buffer_size: usize) -> Result<String> {
+        let by: Vec<String> = by.iter().map(|s| s.to_string()).collect();
+        let mut writer = Vec::new();
+        process_csv_dedupe(
+            &mut input.as_bytes(),
+            &mut writer,
+            OutputFormat::Csv,
+            &CsvDialect::default(),
+            &by,
+            buffer_size,
+        )?;
+        Ok(String::from_utf8(writer)?)
+    }
+
+    #[test]
+    fn test_parse_sort_key() -> Result<()> {
+        let key: SortKey = "Kit Number:num:desc".parse()?;
+        assert_eq!(
+            key,
+            SortKey {
+                column: "Kit Number".to_string(),
+                numeric: true,
+                descending: true,
+            }
+        );
+        assert_eq!(key.to_string(), "Kit Number:num:desc");
+        assert_eq!("Name".parse::<SortKey>()?.to_string(), "Name:str:asc");
+        assert_eq!("Name:DESC".parse::<SortKey>()?.to_string(), "Name:str:desc");
+        assert_eq!("a:b:num".parse::<SortKey>()?.column, "a:b");
+        assert!(":num".parse::<SortKey>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_sort() -> Result<()> {
+        let expected = "Name,Position,Kit Number\n\
+            Mattia Perin,Goalkeeper,37\n\
+            Carlo Pinsoglio,Goalkeeper,31\n\
+            Leonardo Bonucci,Centre-Back,19\n\
+            Mario Mandzukic,Centre-Forward,17\n\
+            Wojciech Szczesny,Goalkeeper,1\n\
+            Moise Kean,Centre-Forward,\n";
+        // buffer_size 为 1 时每一行都是一个 run，测试 k 路归并
+        for buffer_size in [1 << 20, 1, 100] {
+            assert_eq!(sort(INPUT, "Kit Number:num:desc", buffer_size)?, expected);
+        }
+
+        // 相等的行保持原来的顺序
+        let expected = "Name,Position,Kit Number\n\
+            Leonardo Bonucci,Centre-Back,19\n\
+            Mario Mandzukic,Centre-Forward,17\n\
+            Moise Kean,Centre-Forward,\n\
+            Wojciech Szczesny,Goalkeeper,1\n\
+            Mattia Perin,Goalkeeper,37\n\
+            Carlo Pinsoglio,Goalkeeper,31\n";
+        for buffer_size in [1 << 20, 1, 100] {
+            assert_eq!(sort(INPUT, "Position", buffer_size)?, expected);
+        }
+
+        let err = sort(INPUT, "Club", 1).unwrap_err();
+        assert_eq!(err.to_string(), "Unknown column: Club");
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_sort_large() -> Result<()> {
+        let mut input = String::from("Name,Kit Number\n");
+        for i in 0..1000 {
+            input.push_str(&format!("Player {},{}\n", i, (i * 7919) % 1000));
+        }
+        // buffer_size 为 1 时有 1000 个 run，超过 MAX_FAN_IN，溢出时要先合并
+        for buffer_size in [1024, 1] {
+            let ret = sort(&input, "Kit Number:num", buffer_size)?;
+            let numbers: Vec<usize> = ret
+                .lines()
+                .skip(1)
+                .map(|l| l.rsplit(',').next().unwrap().parse().unwrap())
+                .collect();
+            assert_eq!(numbers, (0..1000).collect::<Vec<_>>());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_external_sorter_fan_in() -> Result<()> {
+        let keys = [SortColumn {
+            index: 0,
+            numeric: true,
+            descending: false,
+        }];
+        let mut sorter = ExternalSorter::new(&keys, 1);
+        let n = MAX_FAN_IN * 3 + 5;
+        for i in (0..n).rev() {
+            sorter.push(StringRecord::from(vec![i.to_string()]))?;
+            assert!(sorter.runs.len() + 1 < MAX_FAN_IN);
+        }
+        let numbers = sorter
+            .finish()?
+            .map(|r| Ok(r?[0].parse()?))
+            .collect::<Result<Vec<usize>>>()?;
+        assert_eq!(numbers, (0..n).collect::<Vec<_>>());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sort_non_finite() -> Result<()> {
+        let input = "Code\n3\ninf\nNaN\n-1\nInfinity\n";
+        assert_eq!(
+            sort(input, "Code:num", 1 << 20)?,
+            "Code\n-1\n3\nInfinity\nNaN\ninf\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_dedupe() -> Result<()> {
+        let input = "Name,Position\n\
+            Mattia Perin,Goalkeeper\n\
+            Moise Kean,Centre-Forward\n\
+            Mattia Perin,Reserve\n\
+            Moise Kean,Centre-Forward\n\
+            Carlo Pinsoglio,Goalkeeper\n";
+        for buffer_size in [1 << 20, 1, 60] {
+            assert_eq!(
+                dedupe(input, &["Name"], buffer_size)?,
+                "Name,Position\n\
+                 Mattia Perin,Goalkeeper\n\
+                 Moise Kean,Centre-Forward\n\
+                 Carlo Pinsoglio,Goalkeeper\n"
+            );
+            assert_eq!(
+                dedupe(input, &[], buffer_size)?,
+                "Name,Position\n\
+                 Mattia Perin,Goalkeeper\n\
+                 Moise Kean,Centre-Forward\n\
+                 Mattia Perin,Reserve\n\
+                 Carlo Pinsoglio,Goalkeeper\n"
+            );
+        }
+        Ok(())
+    }
+}
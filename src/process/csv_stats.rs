@@ -0,0 +1,405 @@
+use super::{record_writer, CsvDialect};
+use crate::cli::OutputFormat;
+use anyhow::{anyhow, bail, Result};
+use csv::StringRecord;
+use serde_json::{Map, Value};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    io::{Read, Write},
+    str::FromStr,
+};
+
+/// Group rows by these columns and compute the aggregations per group (the whole file is one
+/// group without `group_by`), plain column stats when neither is given
+#[derive(Debug, Default, Clone)]
+pub struct StatsOptions {
+    pub group_by: Vec<String>,
+    pub aggs: Vec<Aggregation>,
+}
+
+/// An aggregation such as `count` or `avg:Kit Number`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Aggregation {
+    pub func: AggFunc,
+    pub column: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggFunc {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+#[derive(Debug, Default)]
+struct ColumnStats {
+    count: usize,
+    nulls: usize,
+    distinct: HashSet<String>,
+    numeric: Numeric,
+    // 只要有一个非空值不是数字，这一列就不算数值列
+    non_numeric: bool,
+}
+
+// 数值的累计值，分组聚合和列统计都用它
+#[derive(Debug, Default, Clone)]
+struct Numeric {
+    n: usize,
+    sum: f64,
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+pub fn process_csv_stats(
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    format: OutputFormat,
+    dialect: &CsvDialect,
+    opts: &StatsOptions,
+) -> Result<()> {
+    let mut reader = dialect.reader_builder().from_reader(dialect.decode(reader));
+    let mut headers = if dialect.has_headers {
+        reader.headers()?.clone()
+    } else {
+        StringRecord::new()
+    };
+
+    let (headers, rows) = if opts.group_by.is_empty() && opts.aggs.is_empty() {
+        column_stats(&mut reader, &mut headers)?
+    } else {
+        group_stats(&mut reader, &mut headers, opts)?
+    };
+
+    let mut writer = record_writer(writer, format, dialect)?;
+    writer.write_headers(&headers)?;
+    for row in rows {
+        writer.write(&Value::Object(row))?;
+    }
+    writer.finish()
+}
+
+type StatsRows = (Vec<String>, Vec<Map<String, Value>>);
+
+fn column_stats<R: Read>(
+    reader: &mut csv::Reader<R>,
+    headers: &mut StringRecord,
+) -> Result<StatsRows> {
+    let mut stats: Vec<ColumnStats> = Vec::new();
+    let mut record = StringRecord::new();
+    while reader.read_record(&mut record)? {
+        for i in headers.len()..record.len() {
+            headers.push_field(&format!("col{}", i));
+        }
+        stats.resize_with(headers.len(), ColumnStats::default);
+        for (i, cell) in record.iter().enumerate() {
+            stats[i].add(cell);
+        }
+    }
+    // 没有数据行的时候也每一列都输出一行
+    stats.resize_with(headers.len(), ColumnStats::default);
+
+    let columns = ["column", "count", "distinct", "nulls", "min", "max", "mean"];
+    let rows = headers
+        .iter()
+        .zip(stats)
+        .map(|(name, s)| {
+            let numeric = s.count > 0 && !s.non_numeric;
+            let mut row = Map::new();
+            row.insert("column".into(), name.into());
+            row.insert("count".into(), s.count.into());
+            row.insert("distinct".into(), s.distinct.len().into());
+            row.insert("nulls".into(), s.nulls.into());
+            let pick = |v: Option<f64>| if numeric { number(v) } else { Value::Null };
+            row.insert("min".into(), pick(s.numeric.min));
+            row.insert("max".into(), pick(s.numeric.max));
+            row.insert("mean".into(), pick(s.numeric.mean()));
+            row
+        })
+        .collect();
+    Ok((columns.iter().map(|c| c.to_string()).collect(), rows))
+}
+
+fn group_stats<R: Read>(
+    reader: &mut csv::Reader<R>,
+    headers: &mut StringRecord,
+    opts: &StatsOptions,
+) -> Result<StatsRows> {
+    let aggs = if opts.aggs.is_empty() {
+        vec![Aggregation {
+            func: AggFunc::Count,
+            column: None,
+        }]
+    } else {
+        opts.aggs.clone()
+    };
+
+    // 列名转成下标：分组的列，以及每个聚合用到的列（count 不需要列）
+    let resolve = |headers: &StringRecord| -> Result<(Vec<usize>, Vec<Option<usize>>)> {
+        let keys = opts
+            .group_by
+            .iter()
+            .map(|c| position(headers, c))
+            .collect::<Result<Vec<_>>>()?;
+        let values = aggs
+            .iter()
+            .map(|a| a.column.as_ref().map(|c| position(headers, c)).transpose())
+            .collect::<Result<Vec<_>>>()?;
+        Ok((keys, values))
+    };
+    let mut positions = if headers.is_empty() {
+        None
+    } else {
+        Some(resolve(headers)?)
+    };
+
+    // 分组按第一次出现的顺序输出
+    let mut groups: Vec<(Vec<String>, Vec<Numeric>)> = Vec::new();
+    let mut index: HashMap<Vec<String>, usize> = HashMap::new();
+
+    let mut record = StringRecord::new();
+    while reader.read_record(&mut record)? {
+        // 没有表头时，等读到第一行才知道有几列
+        if positions.is_none() {
+            for i in headers.len()..record.len() {
+                headers.push_field(&format!("col{}", i));
+            }
+            positions = Some(resolve(headers)?);
+        }
+        let (keys, values) = positions.as_ref().expect("positions are resolved above");
+
+        let key: Vec<String> = keys
+            .iter()
+            .map(|i| record.get(*i).unwrap_or_default().to_string())
+            .collect();
+        let i = *index.entry(key.clone()).or_insert_with(|| {
+            groups.push((key, vec![Numeric::default(); aggs.len()]));
+            groups.len() - 1
+        });
+        for ((acc, pos), agg) in groups[i].1.iter_mut().zip(values).zip(&aggs) {
+            match pos {
+                // count 不需要列，直接按行计数
+                None => acc.n += 1,
+                // count:列 数的是非空的格子，文本列也能数，和列统计里的 null 一样看 trim 之后是否为空
+                Some(pos) if agg.func == AggFunc::Count => {
+                    if !record.get(*pos).unwrap_or_default().trim().is_empty() {
+                        acc.n += 1;
+                    }
+                }
+                Some(pos) => {
+                    if let Some(v) = parse_number(record.get(*pos).unwrap_or_default()) {
+                        acc.add(v);
+                    }
+                }
+            }
+        }
+    }
+
+    // 不分组时整个文件是一组，没有数据行也输出一行
+    if opts.group_by.is_empty() && groups.is_empty() {
+        groups.push((Vec::new(), vec![Numeric::default(); aggs.len()]));
+    }
+
+    let mut columns = opts.group_by.clone();
+    columns.extend(aggs.iter().map(|a| a.to_string()));
+    let rows = groups
+        .into_iter()
+        .map(|(key, accs)| {
+            let mut row = Map::new();
+            for (name, value) in opts.group_by.iter().zip(key) {
+                row.insert(name.clone(), value.into());
+            }
+            for (agg, acc) in aggs.iter().zip(accs) {
+                row.insert(agg.to_string(), acc.result(agg.func));
+            }
+            row
+        })
+        .collect();
+    Ok((columns, rows))
+}
+
+fn position(headers: &StringRecord, column: &str) -> Result<usize> {
+    headers
+        .iter()
+        .position(|h| h == column)
+        .ok_or_else(|| anyhow!("Unknown column: {}", column))
+}
+
+pub(crate) fn parse_number(s: &str) -> Option<f64> {
+    s.trim().parse::<f64>().ok().filter(|v| v.is_finite())
+}
+
+// 整数就输出整数，避免 1 变成 1.0
+fn number(v: Option<f64>) -> Value {
+    match v {
+        Some(v) if v.fract() == 0.0 && v.abs() < 9_007_199_254_740_992.0 => (v as i64).into(),
+        Some(v) => serde_json::Number::from_f64(v).map_or(Value::Null, Value::Number),
+        None => Value::Null,
+    }
+}
+
+impl ColumnStats {
+    fn add(&mut self, cell: &str) {
+        if cell.trim().is_empty() {
+            self.nulls += 1;
+            return;
+        }
+        self.count += 1;
+        if !self.distinct.contains(cell) {
+            self.distinct.insert(cell.to_string());
+        }
+        match parse_number(cell) {
+            Some(v) => self.numeric.add(v),
+            None => self.non_numeric = true,
+        }
+    }
+}
+
+impl Numeric {
+    fn add(&mut self, v: f64) {
+        self.n += 1;
+        self.sum += v;
+        self.min = Some(self.min.map_or(v, |m| m.min(v)));
+        self.max = Some(self.max.map_or(v, |m| m.max(v)));
+    }
+
+    fn mean(&self) -> Option<f64> {
+        (self.n > 0).then(|| self.sum / self.n as f64)
+    }
+
+    fn result(&self, func: AggFunc) -> Value {
+        match func {
+            AggFunc::Count => self.n.into(),
+            AggFunc::Sum => number(Some(self.sum)),
+            AggFunc::Avg => number(self.mean()),
+            AggFunc::Min => number(self.min),
+            AggFunc::Max => number(self.max),
+        }
+    }
+}
+
+impl FromStr for Aggregation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (func, column) = match s.split_once(':') {
+            Some((func, column)) => (func, Some(column.to_string())),
+            None => (s, None),
+        };
+        let func = match func.trim().to_lowercase().as_str() {
+            "count" => AggFunc::Count,
+            "sum" => AggFunc::Sum,
+            "avg" | "mean" => AggFunc::Avg,
+            "min" => AggFunc::Min,
+            "max" => AggFunc::Max,
+            v => bail!("Unsupported aggregation: {}", v),
+        };
+        if func != AggFunc::Count && column.is_none() {
+            bail!("Aggregation {} needs a column, e.g. {}:Kit Number", s, s);
+        }
+        Ok(Self { func, column })
+    }
+}
+
+impl fmt::Display for Aggregation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let func = match self.func {
+            AggFunc::Count => "count",
+            AggFunc::Sum => "sum",
+            AggFunc::Avg => "avg",
+            AggFunc::Min => "min",
+            AggFunc::Max => "max",
+        };
+        match &self.column {
+            Some(column) => write!(f, "{}({})", func, column),
+            None => write!(f, "{}", func),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &str = "Name,Position,Nationality,Kit Number\n\
+        A,Goalkeeper,Italy,1\n\
+        B,Defender,Brazil,3\n\
+        C,Goalkeeper,Italy,\n\
+        D,Forward,Italy,10\n";
+
+    fn stats(opts: &StatsOptions) -> Result<String> {
+        let mut writer = Vec::new();
+        process_csv_stats(
+            &mut INPUT.as_bytes(),
+            &mut writer,
+            OutputFormat::Csv,
+            &CsvDialect::default(),
+            opts,
+        )?;
+        Ok(String::from_utf8(writer)?)
+    }
+
+    #[test]
+    fn test_process_csv_stats_columns() -> Result<()> {
+        assert_eq!(
+            stats(&StatsOptions::default())?,
+            "column,count,distinct,nulls,min,max,mean\n\
+             Name,4,4,0,,,\n\
+             Position,4,3,0,,,\n\
+             Nationality,4,2,0,,,\n\
+             Kit Number,3,3,1,1,10,4.666666666666667\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_stats_group_by() -> Result<()> {
+        let opts = StatsOptions {
+            group_by: vec!["Nationality".into()],
+            aggs: vec![
+                "count".parse()?,
+                "count:Name".parse()?,
+                "count:Kit Number".parse()?,
+                "avg:Kit Number".parse()?,
+                "max:Kit Number".parse()?,
+            ],
+        };
+        // count:Name 数的是文本列的非空格子，count:Kit Number 不算空的那一格
+        assert_eq!(
+            stats(&opts)?,
+            "Nationality,count,count(Name),count(Kit Number),avg(Kit Number),max(Kit Number)\n\
+             Italy,3,3,2,5.5,10\n\
+             Brazil,1,1,1,3,3\n"
+        );
+
+        let opts = StatsOptions {
+            group_by: vec!["Club".into()],
+            aggs: vec![],
+        };
+        assert!(stats(&opts).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_stats_agg_without_group_by() -> Result<()> {
+        let opts = StatsOptions {
+            group_by: vec![],
+            aggs: vec!["count".parse()?, "sum:Kit Number".parse()?],
+        };
+        assert_eq!(stats(&opts)?, "count,sum(Kit Number)\n4,14\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_aggregation_parse() -> Result<()> {
+        let agg: Aggregation = "avg:Kit Number".parse()?;
+        assert_eq!(agg.func, AggFunc::Avg);
+        assert_eq!(agg.column.as_deref(), Some("Kit Number"));
+        assert_eq!(agg.to_string(), "avg(Kit Number)");
+        assert!("sum".parse::<Aggregation>().is_err());
+        assert!("median:a".parse::<Aggregation>().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,311 @@
+use super::{
+    csv_convert::write_records, value_to_text, CsvDialect, CsvQuery, CsvTypes, RecordWriter,
+};
+use anyhow::{bail, Result};
+use serde_json::Value;
+use std::{
+    collections::VecDeque,
+    io::{Read, Write},
+};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Which rows `rcli csv view` shows and how wide a column can get
+#[derive(Debug, Clone, Copy)]
+pub struct TableOptions {
+    pub head: Option<usize>,
+    pub tail: Option<usize>,
+    /// Cells wider than this are cut off with `…`, 0 means no limit
+    pub max_width: usize,
+}
+
+// 列宽要看过所有行才知道，所以表格只能在 finish 时一起输出；
+// 指定了 --head/--tail 时只保留前后这些行，中间的行只计数，内存占用不随文件变大；
+// 只指定 --head 时多读一行知道后面还有行就停下，不用读完整个文件
+pub(super) struct TableWriter<'a> {
+    writer: &'a mut dyn Write,
+    opts: TableOptions,
+    // 原来的列名用来取值，headers 里的是截断后用来显示的
+    columns: Vec<String>,
+    headers: Vec<Cell>,
+    head: Vec<Vec<Cell>>,
+    tail: VecDeque<Vec<Cell>>,
+    skipped: usize,
+}
+
+struct Cell {
+    text: String,
+    width: usize,
+    // 数字右对齐
+    right: bool,
+}
+
+/// Render CSV as an aligned box-drawing table, plain text so it can be piped into a pager
+pub fn process_csv_view(
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    dialect: &CsvDialect,
+    opts: &TableOptions,
+) -> Result<()> {
+    let mut table = TableWriter::new(writer, *opts);
+    write_records(
+        reader,
+        &mut table,
+        dialect,
+        &CsvTypes::default(),
+        &CsvQuery::default(),
+    )
+}
+
+impl Default for TableOptions {
+    fn default() -> Self {
+        Self {
+            head: None,
+            tail: None,
+            max_width: 40,
+        }
+    }
+}
+
+impl<'a> TableWriter<'a> {
+    pub(super) fn new(writer: &'a mut dyn Write, opts: TableOptions) -> Self {
+        Self {
+            writer,
+            opts,
+            columns: Vec::new(),
+            headers: Vec::new(),
+            head: Vec::new(),
+            tail: VecDeque::new(),
+            skipped: 0,
+        }
+    }
+
+    fn cell(&self, text: &str, right: bool) -> Cell {
+        // 换行和制表符会把表格弄乱，换成空格
+        let mut text: String = text
+            .chars()
+            .map(|c| if c.is_control() { ' ' } else { c })
+            .collect();
+        let mut width = text.width();
+        let max = self.opts.max_width;
+        if max > 0 && width > max {
+            let mut cut = String::new();
+            width = 0;
+            for c in text.chars() {
+                let w = c.width().unwrap_or_default();
+                if width + w > max - 1 {
+                    break;
+                }
+                cut.push(c);
+                width += w;
+            }
+            cut.push('…');
+            text = cut;
+            width += 1;
+        }
+        Cell { text, width, right }
+    }
+
+    fn write_border(&mut self, widths: &[usize], [left, mid, right]: [&str; 3]) -> Result<()> {
+        let line: Vec<String> = widths.iter().map(|w| "─".repeat(w + 2)).collect();
+        writeln!(self.writer, "{}{}{}", left, line.join(mid), right)?;
+        Ok(())
+    }
+
+    fn write_row(writer: &mut dyn Write, widths: &[usize], row: &[Cell]) -> Result<()> {
+        let mut line = String::from("│");
+        for (i, width) in widths.iter().enumerate() {
+            let (text, w, right) = match row.get(i) {
+                Some(cell) => (cell.text.as_str(), cell.width, cell.right),
+                None => ("", 0, false),
+            };
+            let pad = " ".repeat(width - w);
+            if right {
+                line.push_str(&format!(" {}{} │", pad, text));
+            } else {
+                line.push_str(&format!(" {}{} │", text, pad));
+            }
+        }
+        writeln!(writer, "{}", line)?;
+        Ok(())
+    }
+}
+
+impl RecordWriter for TableWriter<'_> {
+    fn write_headers(&mut self, headers: &[String]) -> Result<()> {
+        self.headers = headers.iter().map(|h| self.cell(h, false)).collect();
+        self.columns = headers.to_vec();
+        Ok(())
+    }
+
+    fn write(&mut self, record: &Value) -> Result<()> {
+        let row: Vec<Cell> = self
+            .columns
+            .iter()
+            .map(|h| {
+                let value = record.get(h).unwrap_or(&Value::Null);
+                let text = value_to_text(value);
+                let right = value.is_number() || text.trim().parse::<f64>().is_ok();
+                self.cell(&text, right)
+            })
+            .collect();
+
+        // 都不指定时显示所有行；只指定 --tail 时前面一行都不保留
+        let head = match (self.opts.head, self.opts.tail) {
+            (None, None) => usize::MAX,
+            (head, _) => head.unwrap_or_default(),
+        };
+        if self.head.len() < head {
+            self.head.push(row);
+        } else {
+            self.tail.push_back(row);
+            if self.tail.len() > self.opts.tail.unwrap_or_default() {
+                self.tail.pop_front();
+                self.skipped += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn is_full(&self) -> bool {
+        self.opts.tail.is_none() && self.skipped > 0
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let mut widths: Vec<usize> = self.headers.iter().map(|h| h.width).collect();
+        for row in self.head.iter().chain(&self.tail) {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.width);
+            }
+        }
+        // 省略的行用一行 … 表示
+        for width in widths.iter_mut() {
+            *width = (*width).max(1);
+        }
+
+        self.write_border(&widths, ["┌", "┬", "┐"])?;
+        Self::write_row(self.writer, &widths, &self.headers)?;
+        self.write_border(&widths, ["├", "┼", "┤"])?;
+        for row in &self.head {
+            Self::write_row(self.writer, &widths, row)?;
+        }
+        if self.skipped > 0 {
+            let ellipsis: Vec<Cell> = widths
+                .iter()
+                .map(|_| Cell {
+                    text: "…".to_string(),
+                    width: 1,
+                    right: false,
+                })
+                .collect();
+            Self::write_row(self.writer, &widths, &ellipsis)?;
+        }
+        for row in &self.tail {
+            Self::write_row(self.writer, &widths, row)?;
+        }
+        self.write_border(&widths, ["└", "┴", "┘"])?;
+
+        // 只指定 --head 时没有读完，不知道一共有多少行
+        if self.is_full() {
+            writeln!(self.writer, "first {} rows shown", self.head.len())?;
+            return Ok(());
+        }
+        let total = self.head.len() + self.tail.len() + self.skipped;
+        if self.skipped > 0 {
+            writeln!(self.writer, "{} rows, {} not shown", total, self.skipped)?;
+        }
+        Ok(())
+    }
+
+    fn write_encoded(&mut self, _encoded: &[u8], _records: usize) -> Result<()> {
+        bail!("table output can't be written in parallel")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &str = "Name,Position,Kit Number\n\
+        Wojciech Szczesny,Goalkeeper,1\n\
+        Mattia Perin,Goalkeeper,37\n\
+        Leonardo Bonucci,Centre-Back,19\n\
+        Mario Mandzukic,Centre-Forward,17\n";
+
+    fn view(
+        input: &str,
+        head: Option<usize>,
+        tail: Option<usize>,
+        max_width: usize,
+    ) -> Result<String> {
+        let mut writer = Vec::new();
+        let opts = TableOptions {
+            head,
+            tail,
+            max_width,
+        };
+        process_csv_view(
+            &mut input.as_bytes(),
+            &mut writer,
+            &CsvDialect::default(),
+            &opts,
+        )?;
+        Ok(String::from_utf8(writer)?)
+    }
+
+    #[test]
+    fn test_process_csv_view() -> Result<()> {
+        assert_eq!(
+            view(INPUT, None, None, 40)?,
+            "┌───────────────────┬────────────────┬────────────┐\n\
+             │ Name              │ Position       │ Kit Number │\n\
+             ├───────────────────┼────────────────┼────────────┤\n\
+             │ Wojciech Szczesny │ Goalkeeper     │          1 │\n\
+             │ Mattia Perin      │ Goalkeeper     │         37 │\n\
+             │ Leonardo Bonucci  │ Centre-Back    │         19 │\n\
+             │ Mario Mandzukic   │ Centre-Forward │         17 │\n\
+             └───────────────────┴────────────────┴────────────┘\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_view_head_tail() -> Result<()> {
+        assert_eq!(
+            view(INPUT, Some(1), Some(1), 8)?,
+            "┌──────────┬──────────┬──────────┐\n\
+             │ Name     │ Position │ Kit Num… │\n\
+             ├──────────┼──────────┼──────────┤\n\
+             │ Wojciec… │ Goalkee… │        1 │\n\
+             │ …        │ …        │ …        │\n\
+             │ Mario M… │ Centre-… │       17 │\n\
+             └──────────┴──────────┴──────────┘\n\
+             4 rows, 2 not shown\n"
+        );
+        let ret = view(INPUT, None, Some(2), 0)?;
+        assert!(ret.contains("│ …"));
+        assert!(ret.contains("Leonardo Bonucci"));
+        assert!(!ret.contains("Mattia Perin"));
+        assert!(view(INPUT, Some(10), None, 0)?.ends_with("┘\n"));
+        assert!(view(INPUT, Some(2), None, 0)?.ends_with("┘\nfirst 2 rows shown\n"));
+        // 只指定 --head 时读够行数就停下，后面格式不对的行不会被读到
+        let broken = format!("{}a,b\n", INPUT);
+        assert!(view(&broken, None, None, 0).is_err());
+        assert!(view(&broken, Some(2), None, 0).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_view_wide_chars() -> Result<()> {
+        // 中文字符占两列
+        let ret = view("名字,号码\n布冯,77\n", None, None, 3)?;
+        assert_eq!(
+            ret,
+            "┌─────┬─────┐\n\
+             │ 名… │ 号… │\n\
+             ├─────┼─────┤\n\
+             │ 布… │  77 │\n\
+             └─────┴─────┘\n"
+        );
+        Ok(())
+    }
+}
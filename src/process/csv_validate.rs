@@ -0,0 +1,430 @@
+use super::{csv_schema::parse_typed, load_config, value_to_text, ColumnType, CsvDialect};
+use anyhow::{anyhow, bail, Context, Result};
+use csv::StringRecord;
+use regex::Regex;
+use serde_json::Value;
+use std::{
+    collections::HashSet,
+    fmt,
+    io::{Read, Write},
+    path::Path,
+};
+
+/// Rules every row has to satisfy, loaded from a JSON Schema or a `column: type` spec
+#[derive(Debug, Default, Clone)]
+pub struct ValidationSchema {
+    columns: Vec<ColumnRule>,
+    // JSON Schema 里的 additionalProperties: false，不允许出现 schema 之外的列
+    deny_unknown: bool,
+}
+
+#[derive(Debug, Clone)]
+struct ColumnRule {
+    name: String,
+    ty: Option<ColumnType>,
+    // 列必须存在，并且每一行都不能为空
+    required: bool,
+    // 列必须在表头里出现，简单格式里列出来的列都算
+    present: bool,
+    choices: Vec<String>,
+    minimum: Option<f64>,
+    maximum: Option<f64>,
+    min_length: Option<usize>,
+    max_length: Option<usize>,
+    pattern: Option<Regex>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub line: u64,
+    pub column: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ValidationReport {
+    pub rows: usize,
+    pub invalid_rows: usize,
+    pub violations: Vec<Violation>,
+}
+
+impl ValidationSchema {
+    // 带 properties 的就当作 JSON Schema，否则是 "列名: 类型" 的简单格式
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let value: Value = load_config(path)?;
+        Self::from_value(&value).with_context(|| format!("Invalid schema: {}", path.display()))
+    }
+
+    pub fn from_value(value: &Value) -> Result<Self> {
+        let map = value
+            .as_object()
+            .ok_or_else(|| anyhow!("schema must be an object"))?;
+        if map.contains_key("properties") {
+            return Self::from_json_schema(value);
+        }
+
+        let columns = map
+            .iter()
+            .map(|(name, ty)| {
+                let ty: ColumnType = serde_json::from_value(ty.clone())
+                    .with_context(|| format!("column {:?}: unknown type {}", name, ty))?;
+                Ok(ColumnRule {
+                    ty: Some(ty),
+                    present: true,
+                    ..ColumnRule::new(name)
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            columns,
+            deny_unknown: false,
+        })
+    }
+
+    // 只支持 JSON Schema 里和单元格有关的那部分：type、enum、minimum/maximum、minLength/maxLength、pattern
+    fn from_json_schema(value: &Value) -> Result<Self> {
+        let properties = value["properties"]
+            .as_object()
+            .ok_or_else(|| anyhow!("properties must be an object"))?;
+        let required: HashSet<&str> = value["required"]
+            .as_array()
+            .map(|v| v.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+
+        let mut columns = Vec::with_capacity(properties.len());
+        for (name, prop) in properties {
+            let mut rule = ColumnRule::new(name);
+            rule.required = required.contains(name.as_str());
+            rule.present = rule.required;
+            // ["integer", "null"] 这种写法取第一个不是 null 的类型
+            let ty = match &prop["type"] {
+                Value::Array(types) => types.iter().find(|t| t.as_str() != Some("null")),
+                Value::Null => None,
+                ty => Some(ty),
+            };
+            if let Some(ty) = ty {
+                rule.ty = Some(
+                    serde_json::from_value(ty.clone())
+                        .with_context(|| format!("column {:?}: unsupported type {}", name, ty))?,
+                );
+            }
+            if let Some(choices) = prop["enum"].as_array() {
+                rule.choices = choices.iter().map(value_to_text).collect();
+            }
+            rule.minimum = prop["minimum"].as_f64();
+            rule.maximum = prop["maximum"].as_f64();
+            rule.min_length = prop["minLength"].as_u64().map(|v| v as usize);
+            rule.max_length = prop["maxLength"].as_u64().map(|v| v as usize);
+            if let Some(pattern) = prop["pattern"].as_str() {
+                rule.pattern = Some(
+                    Regex::new(pattern)
+                        .with_context(|| format!("column {:?}: invalid pattern", name))?,
+                );
+            }
+            columns.push(rule);
+        }
+        for name in &required {
+            if !properties.contains_key(*name) {
+                columns.push(ColumnRule {
+                    required: true,
+                    present: true,
+                    ..ColumnRule::new(name)
+                });
+            }
+        }
+
+        Ok(Self {
+            columns,
+            deny_unknown: value["additionalProperties"] == Value::Bool(false),
+        })
+    }
+}
+
+impl ColumnRule {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ty: None,
+            required: false,
+            present: false,
+            choices: Vec::new(),
+            minimum: None,
+            maximum: None,
+            min_length: None,
+            max_length: None,
+            pattern: None,
+        }
+    }
+
+    // 返回第一个不满足的规则，空单元格只检查是否必填
+    fn check(&self, cell: &str) -> Option<String> {
+        if cell.trim().is_empty() {
+            return self.required.then(|| "missing value".to_string());
+        }
+        if let Some(ty) = self.ty {
+            if parse_typed(cell, ty).is_err() {
+                return Some(format!("{:?} is not a valid {}", cell, ty));
+            }
+        }
+        if !self.choices.is_empty() && !self.choices.iter().any(|c| c == cell.trim()) {
+            return Some(format!(
+                "{:?} is not one of {}",
+                cell,
+                self.choices.join(", ")
+            ));
+        }
+        if let Ok(v) = cell.trim().parse::<f64>() {
+            if let Some(min) = self.minimum.filter(|min| v < *min) {
+                return Some(format!("{} is less than the minimum {}", cell, min));
+            }
+            if let Some(max) = self.maximum.filter(|max| v > *max) {
+                return Some(format!("{} is greater than the maximum {}", cell, max));
+            }
+        }
+        let len = cell.chars().count();
+        if let Some(min) = self.min_length.filter(|min| len < *min) {
+            return Some(format!("{:?} is shorter than {} characters", cell, min));
+        }
+        if let Some(max) = self.max_length.filter(|max| len > *max) {
+            return Some(format!("{:?} is longer than {} characters", cell, max));
+        }
+        if let Some(pattern) = self.pattern.as_ref().filter(|p| !p.is_match(cell)) {
+            return Some(format!("{:?} does not match {}", cell, pattern));
+        }
+        None
+    }
+}
+
+/// Check every row against the schema and write one line per violation plus a summary.
+/// With `strict` the first violation is returned as an error instead.
+pub fn process_csv_validate(
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    dialect: &CsvDialect,
+    schema: &ValidationSchema,
+    strict: bool,
+) -> Result<ValidationReport> {
+    let mut reader = dialect.reader_builder().from_reader(dialect.decode(reader));
+    let mut headers = if dialect.has_headers {
+        reader.headers()?.clone()
+    } else {
+        StringRecord::new()
+    };
+    let mut report = ValidationReport::default();
+
+    // 有表头时在读数据之前就检查列，这样空文件也能发现缺少的列；没有表头时等读到第一行再检查
+    let mut positions = None;
+    if !headers.is_empty() {
+        positions = Some(check_headers(
+            schema,
+            &headers,
+            &mut report,
+            writer,
+            strict,
+        )?);
+    }
+
+    let mut record = StringRecord::new();
+    while reader.read_record(&mut record)? {
+        if positions.is_none() {
+            for i in headers.len()..record.len() {
+                headers.push_field(&format!("col{}", i));
+            }
+            positions = Some(check_headers(
+                schema,
+                &headers,
+                &mut report,
+                writer,
+                strict,
+            )?);
+        }
+
+        report.rows += 1;
+        let line = record.position().map(|p| p.line()).unwrap_or_default();
+        let mut valid = true;
+        let positions = positions.as_ref().expect("positions are resolved above");
+        for (rule, pos) in schema.columns.iter().zip(positions) {
+            let Some(pos) = pos else {
+                continue;
+            };
+            // flexible 模式下这一行可能比表头短，当作空单元格
+            let cell = record.get(*pos).unwrap_or_default();
+            if let Some(reason) = rule.check(cell) {
+                valid = false;
+                let v = Violation::new(line, &rule.name, reason);
+                report.add(v, writer, strict)?;
+            }
+        }
+        if !valid {
+            report.invalid_rows += 1;
+        }
+    }
+
+    writeln!(
+        writer,
+        "{} rows checked, {} invalid rows, {} violations",
+        report.rows,
+        report.invalid_rows,
+        report.violations.len()
+    )?;
+    Ok(report)
+}
+
+// 检查表头里缺少的列和不允许出现的列，返回每条规则对应的列下标
+fn check_headers(
+    schema: &ValidationSchema,
+    headers: &StringRecord,
+    report: &mut ValidationReport,
+    writer: &mut dyn Write,
+    strict: bool,
+) -> Result<Vec<Option<usize>>> {
+    let mut positions = Vec::with_capacity(schema.columns.len());
+    for rule in &schema.columns {
+        let pos = headers.iter().position(|h| h == rule.name);
+        if pos.is_none() && rule.present {
+            report.add(
+                Violation::new(1, &rule.name, "column is missing"),
+                writer,
+                strict,
+            )?;
+        }
+        positions.push(pos);
+    }
+    if schema.deny_unknown {
+        for h in headers {
+            if !schema.columns.iter().any(|rule| rule.name == h) {
+                let v = Violation::new(1, h, "column is not allowed by the schema");
+                report.add(v, writer, strict)?;
+            }
+        }
+    }
+    Ok(positions)
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    // strict 模式下遇到第一个错误就停止，否则输出这一条并继续
+    fn add(&mut self, violation: Violation, writer: &mut dyn Write, strict: bool) -> Result<()> {
+        if strict {
+            bail!("{}", violation);
+        }
+        writeln!(writer, "{}", violation)?;
+        self.violations.push(violation);
+        Ok(())
+    }
+}
+
+impl Violation {
+    fn new(line: u64, column: &str, reason: impl Into<String>) -> Self {
+        Self {
+            line,
+            column: column.to_string(),
+            reason: reason.into(),
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {:?}: {}",
+            self.line, self.column, self.reason
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const INPUT: &str = "Name,Position,Kit Number\n\
+        Wojciech Szczesny,Goalkeeper,1\n\
+        Mattia Perin,,ten\n\
+        Danilo,Defender,200\n";
+
+    fn validate(schema: &ValidationSchema, strict: bool) -> Result<(ValidationReport, String)> {
+        let mut writer = Vec::new();
+        let report = process_csv_validate(
+            &mut INPUT.as_bytes(),
+            &mut writer,
+            &CsvDialect::default(),
+            schema,
+            strict,
+        )?;
+        Ok((report, String::from_utf8(writer)?))
+    }
+
+    #[test]
+    fn test_validate_column_types() -> Result<()> {
+        let schema = ValidationSchema::from_value(&json!({"Kit Number": "integer", "DOB": "str"}))?;
+        let (report, output) = validate(&schema, false)?;
+        assert_eq!(report.rows, 3);
+        assert_eq!(report.invalid_rows, 1);
+        assert!(!report.is_valid());
+        assert_eq!(
+            output,
+            "line 1, column \"DOB\": column is missing\n\
+             line 3, column \"Kit Number\": \"ten\" is not a valid integer\n\
+             3 rows checked, 1 invalid rows, 2 violations\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_json_schema() -> Result<()> {
+        let schema = ValidationSchema::from_value(&json!({
+            "type": "object",
+            "properties": {
+                "Name": {"type": "string", "minLength": 2},
+                "Position": {"enum": ["Goalkeeper", "Defender", "Midfielder", "Forward"]},
+                "Kit Number": {"type": ["integer", "null"], "minimum": 1, "maximum": 99},
+            },
+            "required": ["Name", "Position"],
+            "additionalProperties": false,
+        }))?;
+        let (report, _) = validate(&schema, false)?;
+        assert_eq!(report.invalid_rows, 2);
+        assert_eq!(
+            report.violations,
+            vec![
+                Violation::new(3, "Kit Number", "\"ten\" is not a valid integer"),
+                Violation::new(3, "Position", "missing value"),
+                Violation::new(4, "Kit Number", "200 is greater than the maximum 99"),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_strict() -> Result<()> {
+        let schema = ValidationSchema::from_value(&json!({"Kit Number": "integer"}))?;
+        let err = validate(&schema, true).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 3, column \"Kit Number\": \"ten\" is not a valid integer"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_validation_schema_load() -> Result<()> {
+        let schema = ValidationSchema::load("fixtures/juventus.schema.json")?;
+        let mut reader = std::fs::File::open("assets/juventus.csv")?;
+        let report = process_csv_validate(
+            &mut reader,
+            &mut std::io::sink(),
+            &CsvDialect::default(),
+            &schema,
+            true,
+        )?;
+        assert!(report.is_valid());
+        assert!(ValidationSchema::from_value(&json!({"a": "date"})).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,107 @@
+use super::{value_to_text, RecordWriter};
+use anyhow::{anyhow, bail, Result};
+use rust_xlsxwriter::{Format, Workbook, Worksheet};
+use serde_json::Value;
+use std::io::Write;
+
+// xlsx 是 zip 格式，只能在最后一次性写出，所以整个工作表都先放在内存里
+pub(super) struct XlsxWriter<'a> {
+    writer: &'a mut dyn Write,
+    worksheet: Worksheet,
+    headers: Vec<String>,
+    row: u32,
+}
+
+impl<'a> XlsxWriter<'a> {
+    pub(super) fn new(writer: &'a mut dyn Write) -> Self {
+        Self {
+            writer,
+            worksheet: Worksheet::new(),
+            headers: Vec::new(),
+            row: 0,
+        }
+    }
+}
+
+impl RecordWriter for XlsxWriter<'_> {
+    fn write_headers(&mut self, headers: &[String]) -> Result<()> {
+        // 表头加粗并冻结在第一行
+        let bold = Format::new().set_bold();
+        for (col, h) in headers.iter().enumerate() {
+            self.worksheet
+                .write_string_with_format(0, column(col)?, h, &bold)?;
+        }
+        self.worksheet.set_freeze_panes(1, 0)?;
+        self.headers = headers.to_vec();
+        self.row = 1;
+        Ok(())
+    }
+
+    fn write(&mut self, record: &Value) -> Result<()> {
+        // 按推断出的类型写单元格，null 留空
+        for (col, h) in self.headers.iter().enumerate() {
+            let col = column(col)?;
+            match record.get(h) {
+                None | Some(Value::Null) => {}
+                Some(Value::Bool(b)) => {
+                    self.worksheet.write_boolean(self.row, col, *b)?;
+                }
+                Some(Value::Number(n)) => {
+                    let n = n.as_f64().unwrap_or_default();
+                    self.worksheet.write_number(self.row, col, n)?;
+                }
+                Some(v) => {
+                    self.worksheet
+                        .write_string(self.row, col, value_to_text(v))?;
+                }
+            }
+        }
+        self.row += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let mut workbook = Workbook::new();
+        workbook.push_worksheet(std::mem::replace(&mut self.worksheet, Worksheet::new()));
+        self.writer.write_all(&workbook.save_to_buffer()?)?;
+        Ok(())
+    }
+
+    fn write_encoded(&mut self, _encoded: &[u8], _records: usize) -> Result<()> {
+        bail!("xlsx output can't be written in parallel")
+    }
+}
+
+// 列号超过 u16 时直接转换会回绕，写到前面的列里去
+fn column(col: usize) -> Result<u16> {
+    u16::try_from(col).map_err(|_| anyhow!("Too many columns for xlsx: {}", col + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_xlsx_writer() -> Result<()> {
+        let mut buf = Vec::new();
+        let mut writer = XlsxWriter::new(&mut buf);
+        writer.write_headers(&["Name".to_string(), "Kit Number".to_string()])?;
+        writer.write(&json!({"Name": "Mattia Perin", "Kit Number": 37}))?;
+        writer.write(&json!({"Name": "Moise Kean", "Kit Number": null}))?;
+        writer.finish()?;
+        assert_eq!(writer.row, 3);
+        // xlsx 文件其实是一个 zip 包
+        assert!(buf.starts_with(b"PK\x03\x04"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_xlsx_column() {
+        assert_eq!(column(3).unwrap(), 3);
+        assert_eq!(
+            column(65536).unwrap_err().to_string(),
+            "Too many columns for xlsx: 65537"
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use super::{record_writer, CsvDialect};
+use crate::cli::OutputFormat;
+use anyhow::Result;
+use serde::Serialize;
+use std::io::{BufRead, BufReader, Read, Write};
+use zxcvbn::{
+    matching::{patterns::MatchPattern, Match},
+    zxcvbn,
+};
+
+/// What zxcvbn thinks of one password, a record of `rcli genpass audit`
+#[derive(Debug, Clone, Serialize)]
+pub struct PasswordAudit {
+    pub password: String,
+    pub score: u8,
+    pub guesses_log10: f64,
+    /// Online attack throttled to 100 guesses per hour
+    pub crack_time_online_throttled: String,
+    /// Online attack at 10 guesses per second
+    pub crack_time_online: String,
+    /// Offline attack against a slow hash at 10k guesses per second
+    pub crack_time_offline_slow: String,
+    /// Offline attack against a fast hash at 10 billion guesses per second
+    pub crack_time_offline_fast: String,
+    /// The pieces zxcvbn recognized, e.g. `password (dictionary)`
+    pub patterns: Vec<String>,
+    pub warning: Option<String>,
+    pub suggestions: Vec<String>,
+}
+
+/// Audit one password, `user_inputs` are words an attacker would try first,
+/// like the company name or the user name
+pub fn audit_password(password: &str, user_inputs: &[&str]) -> Result<PasswordAudit> {
+    let estimate = zxcvbn(password, user_inputs)?;
+    let times = estimate.crack_times();
+    let (warning, suggestions) = match estimate.feedback() {
+        Some(feedback) => (
+            feedback.warning().map(|w| w.to_string()),
+            feedback
+                .suggestions()
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+        ),
+        None => (None, Vec::new()),
+    };
+    Ok(PasswordAudit {
+        password: password.to_string(),
+        score: estimate.score(),
+        guesses_log10: (estimate.guesses_log10() * 100.0).round() / 100.0,
+        crack_time_online_throttled: times.online_throttling_100_per_hour().to_string(),
+        crack_time_online: times.online_no_throttling_10_per_second().to_string(),
+        crack_time_offline_slow: times.offline_slow_hashing_1e4_per_second().to_string(),
+        crack_time_offline_fast: times.offline_fast_hashing_1e10_per_second().to_string(),
+        patterns: estimate.sequence().iter().filter_map(describe).collect(),
+        warning,
+        suggestions,
+    })
+}
+
+/// Audit passwords read one per line, blank lines are skipped
+pub fn process_genpass_audit(
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    format: OutputFormat,
+    user_inputs: &[String],
+) -> Result<()> {
+    let user_inputs: Vec<&str> = user_inputs.iter().map(|s| s.as_str()).collect();
+    let headers = [
+        "password",
+        "score",
+        "guesses_log10",
+        "crack_time_online_throttled",
+        "crack_time_online",
+        "crack_time_offline_slow",
+        "crack_time_offline_fast",
+        "patterns",
+        "warning",
+        "suggestions",
+    ]
+    .map(String::from);
+
+    let mut writer = record_writer(writer, format, &CsvDialect::default())?;
+    writer.write_headers(&headers)?;
+    for line in BufReader::new(reader).lines() {
+        let line = line?;
+        // 只去掉 Windows 换行留下的 \r，密码前后的空格也算密码的一部分
+        let password = line.trim_end_matches('\r');
+        if password.is_empty() {
+            continue;
+        }
+        let audit = audit_password(password, &user_inputs)?;
+        writer.write(&serde_json::to_value(audit)?)?;
+    }
+    writer.finish()
+}
+
+// 暴力破解不算模式，不显示
+fn describe(m: &Match) -> Option<String> {
+    let kind = match &m.pattern {
+        MatchPattern::Dictionary(d) => {
+            // DictionaryType 没有从 zxcvbn 导出，只能看 Debug 的输出
+            let mut kind = match format!("{:?}", d.dictionary_name).as_str() {
+                "UserInputs" => "user input".to_string(),
+                _ => "dictionary".to_string(),
+            };
+            if d.l33t {
+                kind = format!("l33t {}", kind);
+            }
+            if d.reversed {
+                kind = format!("reversed {}", kind);
+            }
+            kind
+        }
+        MatchPattern::Spatial(_) => "keyboard".to_string(),
+        MatchPattern::Repeat(_) => "repeat".to_string(),
+        MatchPattern::Sequence(_) => "sequence".to_string(),
+        MatchPattern::Regex(_) => "regex".to_string(),
+        MatchPattern::Date(_) => "date".to_string(),
+        MatchPattern::BruteForce => return None,
+    };
+    Some(format!("{} ({})", m.token, kind))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    #[test]
+    fn test_audit_password() -> Result<()> {
+        let audit = audit_password("password123", &[])?;
+        assert_eq!(audit.score, 0);
+        assert_eq!(audit.crack_time_offline_fast, "less than a second");
+        assert!(audit
+            .patterns
+            .contains(&"password123 (dictionary)".to_string()));
+        assert!(audit.warning.is_some());
+        assert!(!audit.suggestions.is_empty());
+
+        // 公司名不在字典里，作为 user_inputs 传进去后就能被识别出来
+        let audit = audit_password("Juventus1897", &["juventus"])?;
+        assert!(audit.patterns.iter().any(|p| p == "Juventus (user input)"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_genpass_audit() -> Result<()> {
+        let input = "qwerty\r\n\nkZ7!vQ2#mW9@xR4$\n";
+        let mut buf = Vec::new();
+        process_genpass_audit(
+            &mut input.as_bytes(),
+            &mut buf,
+            OutputFormat::Json,
+            &["acme".to_string()],
+        )?;
+        let ret: Value = serde_json::from_slice(&buf)?;
+        let ret = ret.as_array().unwrap();
+        assert_eq!(ret.len(), 2);
+        assert_eq!(ret[0]["password"], "qwerty");
+        assert_eq!(ret[0]["score"], 0);
+        assert!(ret[0]["patterns"][0]
+            .as_str()
+            .unwrap()
+            .starts_with("qwerty"));
+        assert_eq!(ret[1]["score"], 4);
+        assert_eq!(ret[1]["warning"], Value::Null);
+        Ok(())
+    }
+}
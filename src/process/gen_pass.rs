@@ -1,63 +1,484 @@
-use rand::seq::SliceRandom;
+use super::{record_writer, CsvDialect, PasswordPolicy};
+use crate::cli::OutputFormat;
+use anyhow::bail;
+use rand::{seq::SliceRandom, CryptoRng, Rng, RngCore};
+use serde::Serialize;
+use std::io::Write;
 use zxcvbn::zxcvbn;
 
-const UPPER: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ"; // 大写的 I 和小写的 l 也不做为密码, O和0也一样
-const LOWER: &[u8] = b"abcdefghijkmnopqrstuvwxyz";
-const NUMBER: &[u8] = b"123456789";
-const SYMBOL: &[u8] = b"!@#$%^&*_"; // 选不容易产生歧义的特殊字符
+/// Shortest and longest password `process_genpass` will generate
+pub const MIN_PASSWORD_LENGTH: u8 = 4;
+pub const MAX_PASSWORD_LENGTH: u8 = 128;
 
-// 函数不要跟CLI传进来的数据结构绑定得太紧，所以这里不直接使用 GenPassOpts 来传参。可以方便以后拆出来单独使用
-pub fn process_genpass(
-    length: u8,
-    upper: bool,
-    lower: bool,
-    number: bool,
-    symbol: bool,
-) -> anyhow::Result<String> {
-    let mut rng = rand::thread_rng();
+const UPPER: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const LOWER: &str = "abcdefghijklmnopqrstuvwxyz";
+pub(super) const NUMBER: &str = "0123456789";
+pub(super) const SYMBOL: &str = "!@#$%^&*_"; // 选不容易产生歧义的特殊字符
+const AMBIGUOUS: &str = "IlO0"; // 大写的 I 和小写的 l 默认不做为密码, O和0也一样
 
-    let mut password = Vec::new();
+// 按策略重新生成的最多次数
+const MAX_POLICY_ATTEMPTS: usize = 10_000;
+
+/// Which characters `process_genpass` picks from
+#[derive(Debug, Clone)]
+pub struct GenPassOptions {
+    pub length: u8,
+    pub upper: bool,
+    pub lower: bool,
+    pub number: bool,
+    pub symbol: bool,
+    /// Pick only from these characters, the class switches above are ignored
+    pub charset: Option<String>,
+    /// Characters that never appear in the password
+    pub exclude: String,
+    /// Use these symbols instead of the default ones
+    pub symbols: Option<String>,
+    /// Keep the characters that are easy to mix up: I, l, O and 0
+    pub ambiguous: bool,
+    /// Keep generating until the password follows this policy
+    pub policy: Option<PasswordPolicy>,
+}
+
+/// Strength of one generated password, a record of `rcli genpass --format`
+#[derive(Debug, Clone, Serialize)]
+pub struct PasswordReport {
+    pub password: String,
+    pub score: u8,
+    /// Bits of entropy from how the password was generated
+    pub entropy: f64,
+    pub guesses_log10: f64,
+    /// zxcvbn's crack time against a slow hash at 10k guesses per second
+    pub crack_time: String,
+}
 
-    let mut chars: Vec<u8> = Vec::new();
+// 函数不要跟CLI传进来的数据结构绑定得太紧，所以这里不直接使用 GenPassOpts 来传参。可以方便以后拆出来单独使用
+pub fn process_genpass(opts: &GenPassOptions) -> anyhow::Result<String> {
+    process_genpass_with_rng(opts, &mut rand::thread_rng())
+}
 
-    if upper {
-        chars.extend_from_slice(UPPER);
-        password.push(*UPPER.choose(&mut rng).expect("UPPER won't be empty"));
+/// Same as `process_genpass` but with the caller's random number generator,
+/// a seeded one makes the output reproducible in tests
+pub fn process_genpass_with_rng<R: RngCore + CryptoRng>(
+    opts: &GenPassOptions,
+    rng: &mut R,
+) -> anyhow::Result<String> {
+    if !(MIN_PASSWORD_LENGTH..=MAX_PASSWORD_LENGTH).contains(&opts.length) {
+        bail!(
+            "Password length must be between {} and {}, got {}",
+            MIN_PASSWORD_LENGTH,
+            MAX_PASSWORD_LENGTH,
+            opts.length
+        );
     }
-    if lower {
-        chars.extend_from_slice(LOWER);
-        password.push(*LOWER.choose(&mut rng).expect("LOWER won't be empty"));
+    // 先检查参数，否则没有字符可选时 choose 会返回 None，长度小于字符类别数时减法会溢出
+    let classes = opts.classes()?;
+    if (opts.length as usize) < classes.len() {
+        bail!(
+            "Password length {} is too short to contain all {} enabled character classes",
+            opts.length,
+            classes.len()
+        );
     }
-    if number {
-        chars.extend_from_slice(NUMBER);
-        password.push(*NUMBER.choose(&mut rng).expect("NUMBER won't be empty"));
+
+    if let Some(policy) = &opts.policy {
+        let length = opts.length as usize;
+        if let Some(min) = policy.min_length.filter(|min| length < *min) {
+            bail!(
+                "Password length {} is shorter than the policy's min_length {}",
+                length,
+                min
+            );
+        }
+        if let Some(max) = policy.max_length.filter(|max| length > *max) {
+            bail!(
+                "Password length {} is longer than the policy's max_length {}",
+                length,
+                max
+            );
+        }
     }
-    if symbol {
-        chars.extend_from_slice(SYMBOL);
-        password.push(*SYMBOL.choose(&mut rng).expect("SYMBOL won't be empty"));
+
+    let mut chars: Vec<char> = Vec::new();
+    for class in &classes {
+        for c in class {
+            if !chars.contains(c) {
+                chars.push(*c);
+            }
+        }
     }
 
-    for _ in 0..(length - password.len() as u8) {
+    // 有策略时不断重新生成，直到满足所有规则；规则和字符设置冲突时不会无限循环下去
+    for _ in 0..MAX_POLICY_ATTEMPTS {
+        let password = generate(&classes, &chars, opts.length as usize, rng);
+        match &opts.policy {
+            Some(policy) if !policy.allows(&password) => continue,
+            // 这里不再输出密码和强度，由调用方决定怎么输出，否则批量生成时密码会被打印两次
+            _ => return Ok(password),
+        }
+    }
+    bail!(
+        "Couldn't generate a password that satisfies the policy in {} attempts, check that it fits the enabled characters and length",
+        MAX_POLICY_ATTEMPTS
+    )
+}
+
+fn generate(classes: &[Vec<char>], chars: &[char], length: usize, rng: &mut impl Rng) -> String {
+    let mut password = Vec::new();
+
+    // 每种字符至少选一个
+    for class in classes {
+        password.push(*class.choose(rng).expect("classes are never empty"));
+    }
+
+    // 剩下的从所有字符里选
+    for _ in password.len()..length {
         // 由于 choose 返回的是一个 Option，不会empty报错，所以这里可以直接使用 expect
-        // 如果得到的数据是引用类型的话，要 clone，但这里是 u8，所以不用 clone
+        // 如果得到的数据是引用类型的话，要 clone，但这里是 char，所以不用 clone
         let c = chars
-            .choose(&mut rng)
+            .choose(rng)
             .expect("chars won't be empty in this context");
         password.push(*c)
     }
 
-    password.shuffle(&mut rng);
+    password.shuffle(rng);
 
-    let password = String::from_utf8(password)?;
-    println!("{}", password);
+    // String::from_utf8(password)
+    password.into_iter().collect()
+}
 
+/// Estimate the strength of a password, `entropy` comes from whoever generated it
+pub fn password_report(password: String, entropy: f64) -> anyhow::Result<PasswordReport> {
     let estimate = zxcvbn(&password, &[])?;
+    Ok(PasswordReport {
+        score: estimate.score(),
+        // 保留一两位小数就够了
+        entropy: (entropy * 10.0).round() / 10.0,
+        guesses_log10: (estimate.guesses_log10() * 100.0).round() / 100.0,
+        crack_time: estimate
+            .crack_times()
+            .offline_slow_hashing_1e4_per_second()
+            .to_string(),
+        password,
+    })
+}
+
+/// Write password reports in any of the csv output formats, one record per password
+pub fn process_password_reports(
+    writer: &mut dyn Write,
+    format: OutputFormat,
+    reports: &[PasswordReport],
+) -> anyhow::Result<()> {
+    let headers = [
+        "password",
+        "score",
+        "entropy",
+        "guesses_log10",
+        "crack_time",
+    ]
+    .map(String::from);
+    let mut writer = record_writer(writer, format, &CsvDialect::default())?;
+    writer.write_headers(&headers)?;
+    for report in reports {
+        writer.write(&serde_json::to_value(report)?)?;
+    }
+    writer.finish()
+}
+
+impl GenPassOptions {
+    /// Bits of entropy of a password generated with these options, every character is
+    /// counted as a uniform pick from all the enabled characters
+    pub fn entropy(&self) -> anyhow::Result<f64> {
+        let mut chars: Vec<char> = Vec::new();
+        for c in self.classes()?.into_iter().flatten() {
+            if !chars.contains(&c) {
+                chars.push(c);
+            }
+        }
+        Ok(self.length as f64 * (chars.len() as f64).log2())
+    }
+
+    // 每种打开的字符类别各自的字符，已经去掉了 exclude 里的字符
+    fn classes(&self) -> anyhow::Result<Vec<Vec<char>>> {
+        let classes: Vec<(&str, String)> = match &self.charset {
+            Some(charset) => vec![("charset", charset.clone())],
+            None => {
+                // 自定义的字符不做歧义过滤，用户给了什么就用什么
+                let keep = |set: &str| -> String {
+                    set.chars()
+                        .filter(|c| self.ambiguous || !AMBIGUOUS.contains(*c))
+                        .collect()
+                };
+                let symbols = self.symbols.as_deref().unwrap_or(SYMBOL);
+                [
+                    (self.upper, "uppercase", keep(UPPER)),
+                    (self.lower, "lowercase", keep(LOWER)),
+                    (self.number, "number", keep(NUMBER)),
+                    (self.symbol, "symbol", symbols.to_string()),
+                ]
+                .into_iter()
+                .filter(|(enabled, _, _)| *enabled)
+                .map(|(_, name, set)| (name, set))
+                .collect()
+            }
+        };
+        if classes.is_empty() {
+            bail!("At least one of uppercase, lowercase, number and symbol must be enabled");
+        }
 
-    // 用 eprintln 是为了输出到 std error，如果程序需要输出密码到文件，如 cargo run -- genpass > out.txt
-    // 不会与 std out 的数据混合，即运行 cargo run -- genpass > out.txt，只会输出 Password strength
-    // 如果用 println 的话，在输出到文件时，会把 println 的内容也输出到文件
-    eprintln!("Password strength: {}", estimate.score()); // 16位的长度是4，4表示足够强了
+        classes
+            .into_iter()
+            .map(|(name, set)| {
+                let mut chars: Vec<char> = Vec::new();
+                for c in set.chars() {
+                    if !self.exclude.contains(c) && !chars.contains(&c) {
+                        chars.push(c);
+                    }
+                }
+                if chars.is_empty() {
+                    bail!("No {} characters left to choose from", name);
+                }
+                Ok(chars)
+            })
+            .collect()
+    }
+}
+
+impl Default for GenPassOptions {
+    fn default() -> Self {
+        Self {
+            length: 16,
+            upper: true,
+            lower: true,
+            number: true,
+            symbol: true,
+            charset: None,
+            exclude: String::new(),
+            symbols: None,
+            ambiguous: false,
+            policy: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    #[test]
+    fn test_process_genpass_all_class_combinations() -> anyhow::Result<()> {
+        // 4 个开关的所有组合，0 表示全部关闭
+        for mask in 0..16u8 {
+            let [upper, lower, number, symbol] = [1, 2, 4, 8].map(|bit| mask & bit != 0);
+            let classes: Vec<&str> = [
+                (upper, UPPER),
+                (lower, LOWER),
+                (number, NUMBER),
+                (symbol, SYMBOL),
+            ]
+            .into_iter()
+            .filter_map(|(enabled, chars)| enabled.then_some(chars))
+            .collect();
+            for length in [MIN_PASSWORD_LENGTH, 16, MAX_PASSWORD_LENGTH] {
+                let opts = GenPassOptions {
+                    length,
+                    upper,
+                    lower,
+                    number,
+                    symbol,
+                    ..Default::default()
+                };
+                let ret = process_genpass(&opts);
+                if classes.is_empty() {
+                    assert!(ret.is_err());
+                    continue;
+                }
+                let password = ret?;
+                assert_eq!(password.len(), length as usize);
+                // 每种打开的字符至少出现一次，并且不会出现关闭的字符和容易混淆的字符
+                for chars in &classes {
+                    assert!(password.chars().any(|c| chars.contains(c)), "{}", password);
+                }
+                assert!(password
+                    .chars()
+                    .all(|c| classes.iter().any(|chars| chars.contains(c))));
+                assert!(!password.chars().any(|c| AMBIGUOUS.contains(c)));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_genpass_length_policy() {
+        let opts = |length| GenPassOptions {
+            length,
+            ..Default::default()
+        };
+        let err = process_genpass(&opts(3)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Password length must be between 4 and 128, got 3"
+        );
+        assert!(process_genpass(&opts(0)).is_err());
+        assert!(process_genpass(&opts(MAX_PASSWORD_LENGTH + 1)).is_err());
+        let none = GenPassOptions {
+            upper: false,
+            lower: false,
+            number: false,
+            symbol: false,
+            ..Default::default()
+        };
+        let err = process_genpass(&none).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "At least one of uppercase, lowercase, number and symbol must be enabled"
+        );
+    }
+
+    #[test]
+    fn test_process_genpass_charset() -> anyhow::Result<()> {
+        let opts = GenPassOptions {
+            length: 64,
+            charset: Some("abc123".to_string()),
+            exclude: "3".to_string(),
+            ..Default::default()
+        };
+        let password = process_genpass(&opts)?;
+        assert!(
+            password.chars().all(|c| "abc12".contains(c)),
+            "{}",
+            password
+        );
+
+        let opts = GenPassOptions {
+            charset: Some("ab".to_string()),
+            exclude: "ab".to_string(),
+            ..Default::default()
+        };
+        let err = process_genpass(&opts).unwrap_err();
+        assert_eq!(err.to_string(), "No charset characters left to choose from");
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_genpass_symbols_and_exclude() -> anyhow::Result<()> {
+        let opts = GenPassOptions {
+            length: 64,
+            upper: false,
+            lower: false,
+            symbols: Some("-+".to_string()),
+            exclude: "123456789".to_string(),
+            ..Default::default()
+        };
+        // 数字只剩下 0，而 0 默认是被去掉的
+        let err = process_genpass(&opts).unwrap_err();
+        assert_eq!(err.to_string(), "No number characters left to choose from");
 
-    // Ok(String::from_utf8(password)?)
-    Ok(password)
+        let opts = GenPassOptions {
+            ambiguous: true,
+            ..opts
+        };
+        let password = process_genpass(&opts)?;
+        assert!(password.chars().all(|c| "0-+".contains(c)), "{}", password);
+        assert!(password.contains('0') && (password.contains('-') || password.contains('+')));
+        Ok(())
+    }
+
+    #[test]
+    fn test_genpass_entropy() -> anyhow::Result<()> {
+        let opts = GenPassOptions {
+            length: 10,
+            charset: Some("0123456789abcdef".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(opts.entropy()?, 40.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_password_reports() -> anyhow::Result<()> {
+        let reports = vec![
+            password_report("password".to_string(), 37.6)?,
+            password_report("correct-horse-battery-staple".to_string(), 44.04)?,
+        ];
+        assert_eq!(reports[0].score, 0);
+        assert_eq!(reports[0].crack_time, "less than a second");
+        assert_eq!(reports[1].entropy, 44.0);
+
+        let mut buf = Vec::new();
+        process_password_reports(&mut buf, OutputFormat::Csv, &reports)?;
+        let csv = String::from_utf8(buf)?;
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next(),
+            Some("password,score,entropy,guesses_log10,crack_time")
+        );
+        assert!(lines.next().unwrap().starts_with("password,0,37.6,"));
+        assert_eq!(lines.count(), 1);
+
+        let mut buf = Vec::new();
+        process_password_reports(&mut buf, OutputFormat::Json, &reports)?;
+        let json: Value = serde_json::from_slice(&buf)?;
+        assert_eq!(json[1]["password"], "correct-horse-battery-staple");
+        assert_eq!(json[1]["score"], reports[1].score);
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_genpass_policy() -> anyhow::Result<()> {
+        let policy = PasswordPolicy {
+            min_upper: 3,
+            min_number: 3,
+            max_repeat: Some(1),
+            forbidden: vec!["a".to_string()],
+            no_sequences: true,
+            ..Default::default()
+        };
+        let opts = GenPassOptions {
+            length: 12,
+            policy: Some(policy.clone()),
+            ..Default::default()
+        };
+        for _ in 0..20 {
+            let password = process_genpass(&opts)?;
+            assert!(policy.allows(&password), "{}", password);
+        }
+
+        // 不可能满足的策略
+        let opts = GenPassOptions {
+            policy: Some(PasswordPolicy {
+                min_symbol: 1,
+                ..Default::default()
+            }),
+            symbol: false,
+            ..opts
+        };
+        assert!(process_genpass(&opts).is_err());
+        let opts = GenPassOptions {
+            policy: Some(PasswordPolicy {
+                min_length: Some(20),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let err = process_genpass(&opts).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Password length 16 is shorter than the policy's min_length 20"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_genpass_with_rng() -> anyhow::Result<()> {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let opts = GenPassOptions::default();
+        let a = process_genpass_with_rng(&opts, &mut StdRng::seed_from_u64(42))?;
+        let b = process_genpass_with_rng(&opts, &mut StdRng::seed_from_u64(42))?;
+        let c = process_genpass_with_rng(&opts, &mut StdRng::seed_from_u64(43))?;
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        Ok(())
+    }
 }
@@ -0,0 +1,190 @@
+use super::gen_pass::{NUMBER, SYMBOL};
+use anyhow::{bail, Result};
+use rand::{seq::SliceRandom, CryptoRng, Rng, RngCore};
+use std::{collections::HashSet, fs, path::Path};
+
+/// How `process_passphrase` builds a passphrase
+#[derive(Debug, Clone)]
+pub struct PassphraseOptions {
+    pub words: u8,
+    pub separator: String,
+    /// Upper-case the first letter of every word
+    pub capitalize: bool,
+    /// Append a random digit to one random word
+    pub digit: bool,
+    /// Append a random symbol to one random word
+    pub symbol: bool,
+}
+
+/// A generated passphrase and how many bits of entropy it carries
+#[derive(Debug, Clone)]
+pub struct Passphrase {
+    pub phrase: String,
+    pub entropy: f64,
+}
+
+/// Words a passphrase is picked from, duplicates are removed
+#[derive(Debug, Clone)]
+pub struct Wordlist {
+    words: Vec<String>,
+}
+
+/// Pick random words from the wordlist, entropy only counts the random choices,
+/// so capitalizing every word or changing the separator adds nothing
+pub fn process_passphrase(opts: &PassphraseOptions, wordlist: &Wordlist) -> Result<Passphrase> {
+    process_passphrase_with_rng(opts, wordlist, &mut rand::thread_rng())
+}
+
+/// Same as `process_passphrase` but with the caller's random number generator
+pub fn process_passphrase_with_rng<R: RngCore + CryptoRng>(
+    opts: &PassphraseOptions,
+    wordlist: &Wordlist,
+    rng: &mut R,
+) -> Result<Passphrase> {
+    if opts.words == 0 {
+        bail!("A passphrase needs at least one word");
+    }
+    let n = opts.words as usize;
+
+    let mut words: Vec<String> = (0..n)
+        .map(|_| {
+            let word = wordlist.words.choose(rng).expect("wordlist is never empty");
+            if opts.capitalize {
+                capitalize(word)
+            } else {
+                word.clone()
+            }
+        })
+        .collect();
+    let mut entropy = n as f64 * (wordlist.len() as f64).log2();
+
+    // 数字和特殊字符接在随机选的一个单词后面，除了字符本身，选哪个单词也算熵
+    for (enabled, chars) in [(opts.digit, NUMBER), (opts.symbol, SYMBOL)] {
+        if !enabled {
+            continue;
+        }
+        let chars: Vec<char> = chars.chars().collect();
+        let c = chars.choose(rng).expect("chars won't be empty");
+        words[rng.gen_range(0..n)].push(*c);
+        entropy += (chars.len() as f64).log2() + (n as f64).log2();
+    }
+
+    Ok(Passphrase {
+        phrase: words.join(&opts.separator),
+        entropy,
+    })
+}
+
+impl Wordlist {
+    /// The BIP39 English list: 2048 common words, none of them a prefix of another
+    pub fn builtin() -> Self {
+        let words = bip39::Language::English.word_list();
+        Self {
+            words: words.iter().map(|w| w.to_string()).collect(),
+        }
+    }
+
+    /// Load a wordlist with one word per line, diceware lists such as the EFF large list
+    /// (`11111<TAB>abacus`) work too, the dice numbers are skipped
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    fn parse(content: &str) -> Result<Self> {
+        let mut seen = HashSet::new();
+        let mut words: Vec<String> = Vec::new();
+        for line in content.lines() {
+            // 取每行的最后一列，前面的骰子编号不要
+            let Some(word) = line.split_whitespace().last() else {
+                continue;
+            };
+            if seen.insert(word) {
+                words.push(word.to_string());
+            }
+        }
+        if words.len() < 2 {
+            bail!("Wordlist needs at least 2 different words");
+        }
+        Ok(Self { words })
+    }
+
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+impl Default for PassphraseOptions {
+    fn default() -> Self {
+        Self {
+            words: 6,
+            separator: "-".to_string(),
+            capitalize: false,
+            digit: false,
+            symbol: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_process_passphrase() -> Result<()> {
+        let wordlist = Wordlist::builtin();
+        assert_eq!(wordlist.len(), 2048);
+        let ret = process_passphrase(&PassphraseOptions::default(), &wordlist)?;
+        let words: Vec<&str> = ret.phrase.split('-').collect();
+        assert_eq!(words.len(), 6);
+        assert!(words.iter().all(|w| wordlist.words.iter().any(|l| l == w)));
+        // 每个单词 11 位
+        assert_eq!(ret.entropy, 66.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_passphrase_options() -> Result<()> {
+        let wordlist =
+            Wordlist::parse("11111\tabacus\n11112\tabdomen\n\n11113\tabdominal\n11114\tabide\n")?;
+        assert_eq!(wordlist.len(), 4);
+        let opts = PassphraseOptions {
+            words: 4,
+            separator: " ".to_string(),
+            capitalize: true,
+            digit: true,
+            symbol: true,
+        };
+        let ret = process_passphrase(&opts, &wordlist)?;
+        let words: Vec<&str> = ret.phrase.split(' ').collect();
+        assert_eq!(words.len(), 4);
+        assert!(words.iter().all(|w| w.starts_with("Ab")));
+        assert_eq!(ret.phrase.chars().filter(|c| c.is_ascii_digit()).count(), 1);
+        assert_eq!(
+            ret.phrase.chars().filter(|c| SYMBOL.contains(*c)).count(),
+            1
+        );
+        // 4 个单词各 2 位，数字 log2(10) + 位置 2 位，特殊字符 log2(9) + 位置 2 位
+        let expected = 8.0 + 10f64.log2() + 2.0 + 9f64.log2() + 2.0;
+        assert!((ret.entropy - expected).abs() < 1e-9);
+
+        let opts = PassphraseOptions {
+            words: 0,
+            ..Default::default()
+        };
+        assert!(process_passphrase(&opts, &wordlist).is_err());
+        assert!(Wordlist::parse("only\nonly\n").is_err());
+        Ok(())
+    }
+}
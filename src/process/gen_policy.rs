@@ -0,0 +1,213 @@
+use super::load_config;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{fmt, path::Path};
+
+// 连续几个字符算作一个序列，比如 abc、321
+const SEQUENCE_LENGTH: usize = 3;
+
+// 判断字符属于哪一类
+type CharClass = fn(&char) -> bool;
+
+/// Rules a password has to follow, loaded from a TOML or YAML file, e.g. `min_upper = 2`
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PasswordPolicy {
+    pub min_length: Option<usize>,
+    pub max_length: Option<usize>,
+    pub min_upper: usize,
+    pub min_lower: usize,
+    pub min_number: usize,
+    /// Anything that is not a letter or a digit counts as a symbol
+    pub min_symbol: usize,
+    /// How many times in a row the same character may appear
+    pub max_repeat: Option<usize>,
+    /// Substrings that may not appear, compared case-insensitively
+    pub forbidden: Vec<String>,
+    /// Reject runs of three ascending or descending letters or digits like `abc` or `321`
+    pub no_sequences: bool,
+}
+
+/// A policy rule a password breaks, named after the field in the policy file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyViolation {
+    pub rule: &'static str,
+    pub message: String,
+}
+
+impl PasswordPolicy {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        load_config(path)
+    }
+
+    /// Check a password against every rule, an empty list means the password is fine
+    pub fn check(&self, password: &str) -> Vec<PolicyViolation> {
+        let mut violations = Vec::new();
+        let mut fail = |rule, message: String| violations.push(PolicyViolation { rule, message });
+        let chars: Vec<char> = password.chars().collect();
+
+        if let Some(min) = self.min_length.filter(|min| chars.len() < *min) {
+            fail(
+                "min_length",
+                format!("needs at least {} characters, got {}", min, chars.len()),
+            );
+        }
+        if let Some(max) = self.max_length.filter(|max| chars.len() > *max) {
+            fail(
+                "max_length",
+                format!("allows at most {} characters, got {}", max, chars.len()),
+            );
+        }
+
+        let classes: [(&str, &str, usize, CharClass); 4] = [
+            ("min_upper", "uppercase letters", self.min_upper, |c| {
+                c.is_uppercase()
+            }),
+            ("min_lower", "lowercase letters", self.min_lower, |c| {
+                c.is_lowercase()
+            }),
+            ("min_number", "digits", self.min_number, |c| c.is_numeric()),
+            ("min_symbol", "symbols", self.min_symbol, |c| {
+                !c.is_alphanumeric()
+            }),
+        ];
+        for (rule, name, min, is_class) in classes {
+            let count = chars.iter().filter(|c| is_class(c)).count();
+            if count < min {
+                fail(
+                    rule,
+                    format!("needs at least {} {}, got {}", min, name, count),
+                );
+            }
+        }
+
+        if let Some(max) = self.max_repeat {
+            // 找出最长的一段相同字符
+            let mut longest = (0, ' ');
+            let mut run = 0;
+            for (i, c) in chars.iter().enumerate() {
+                run = if i > 0 && chars[i - 1] == *c {
+                    run + 1
+                } else {
+                    1
+                };
+                if run > longest.0 {
+                    longest = (run, *c);
+                }
+            }
+            if longest.0 > max {
+                fail(
+                    "max_repeat",
+                    format!(
+                        "allows a character at most {} times in a row, '{}' appears {} times",
+                        max, longest.1, longest.0
+                    ),
+                );
+            }
+        }
+
+        let lower = password.to_lowercase();
+        for word in &self.forbidden {
+            if !word.is_empty() && lower.contains(&word.to_lowercase()) {
+                fail("forbidden", format!("contains \"{}\"", word));
+            }
+        }
+
+        if self.no_sequences {
+            if let Some(seq) = find_sequence(&chars) {
+                fail("no_sequences", format!("contains the sequence \"{}\"", seq));
+            }
+        }
+        violations
+    }
+
+    pub fn allows(&self, password: &str) -> bool {
+        self.check(password).is_empty()
+    }
+}
+
+// 字母不区分大小写，只看 ASCII 的字母和数字
+fn find_sequence(chars: &[char]) -> Option<String> {
+    chars.windows(SEQUENCE_LENGTH).find_map(|w| {
+        if !w.iter().all(|c| c.is_ascii_alphanumeric()) {
+            return None;
+        }
+        let codes: Vec<i32> = w.iter().map(|c| c.to_ascii_lowercase() as i32).collect();
+        let step = codes[1] - codes[0];
+        let is_sequence = step.abs() == 1 && codes.windows(2).all(|p| p[1] - p[0] == step);
+        is_sequence.then(|| w.iter().collect())
+    })
+}
+
+impl fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.rule, self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const POLICY: &str = r#"
+min_length = 10
+max_length = 20
+min_upper = 2
+min_number = 1
+min_symbol = 1
+max_repeat = 2
+forbidden = ["juventus"]
+no_sequences = true
+"#;
+
+    #[test]
+    fn test_password_policy_check() -> Result<()> {
+        let policy: PasswordPolicy = toml::from_str(POLICY)?;
+        assert!(policy.allows("Fo!Ba7rQxz"));
+
+        let rules: Vec<&str> = policy
+            .check("JUVENTUSaaa123")
+            .iter()
+            .map(|v| v.rule)
+            .collect();
+        assert_eq!(
+            rules,
+            ["min_symbol", "max_repeat", "forbidden", "no_sequences"]
+        );
+
+        let violations = policy.check("short");
+        assert_eq!(
+            violations[0].to_string(),
+            "min_length: needs at least 10 characters, got 5"
+        );
+        assert_eq!(
+            violations[1].to_string(),
+            "min_upper: needs at least 2 uppercase letters, got 0"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_sequence() {
+        let seq = |s: &str| find_sequence(&s.chars().collect::<Vec<_>>());
+        assert_eq!(seq("xAbCy"), Some("AbC".to_string()));
+        assert_eq!(seq("q987"), Some("987".to_string()));
+        assert_eq!(seq("ab-c"), None);
+        assert_eq!(seq("aceg"), None);
+    }
+
+    #[test]
+    fn test_password_policy_load() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("policy.yaml");
+        fs::write(&path, "min_length: 12\nforbidden: [acme]\n")?;
+        let policy = PasswordPolicy::load(&path)?;
+        assert_eq!(policy.min_length, Some(12));
+        assert_eq!(policy.forbidden, ["acme"]);
+
+        fs::write(&path, "min_lenght: 12\n")?;
+        assert!(PasswordPolicy::load(&path).is_err());
+        Ok(())
+    }
+}
@@ -1,11 +1,64 @@
 mod b64;
 mod csv_convert;
+mod csv_diff;
+mod csv_join;
+mod csv_parallel;
+mod csv_parquet;
+mod csv_query;
+mod csv_reverse;
+mod csv_schema;
+mod csv_sort;
+mod csv_stats;
+mod csv_table;
+mod csv_validate;
+mod csv_xlsx;
+mod gen_audit;
 mod gen_pass;
+mod gen_passphrase;
+mod gen_policy;
 mod http_serve;
 mod text;
 
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use std::{fs, path::Path};
+
 pub use b64::{process_decode, process_encode};
-pub use csv_convert::process_csv;
-pub use gen_pass::process_genpass;
+pub use csv_convert::{
+    process_csv, process_csv_stream, record_writer, value_to_text, CsvDialect, RecordWriter,
+};
+pub use csv_diff::{process_csv_diff, CellChange, CsvDiff, ModifiedRow};
+pub use csv_join::{process_csv_concat, process_csv_join};
+pub use csv_parallel::process_csv_parallel;
+pub use csv_query::{BoundQuery, CmpOp, CsvQuery, Expr, Operand};
+pub use csv_reverse::{process_reverse, process_reverse_stream, FlattenOptions, JsonField};
+pub use csv_schema::{ColumnType, CsvSchema, CsvTypes};
+pub use csv_sort::{process_csv_dedupe, process_csv_sort, SortKey};
+pub use csv_stats::{process_csv_stats, AggFunc, Aggregation, StatsOptions};
+pub use csv_table::{process_csv_view, TableOptions};
+pub use csv_validate::{process_csv_validate, ValidationReport, ValidationSchema, Violation};
+pub use gen_audit::{audit_password, process_genpass_audit, PasswordAudit};
+pub use gen_pass::{
+    password_report, process_genpass, process_genpass_with_rng, process_password_reports,
+    GenPassOptions, PasswordReport, MAX_PASSWORD_LENGTH, MIN_PASSWORD_LENGTH,
+};
+pub use gen_passphrase::{
+    process_passphrase, process_passphrase_with_rng, Passphrase, PassphraseOptions, Wordlist,
+};
+pub use gen_policy::{PasswordPolicy, PolicyViolation};
 pub use http_serve::process_http_serve;
-pub use text::{process_text_generate, process_text_sign, process_text_verify};
+pub use text::{
+    process_text_generate, process_text_generate_with_rng, process_text_sign, process_text_verify,
+    Blake3, Ed25519Signer, Ed25519Verifier,
+};
+
+// 读取 schema、策略这类配置文件：toml 单独处理，json 是 yaml 的子集，所以其他情况都用 serde_yaml 解析
+pub(crate) fn load_config<T: DeserializeOwned>(path: impl AsRef<Path>) -> Result<T> {
+    let path = path.as_ref();
+    let content = fs::read_to_string(path)?;
+    let config = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => toml::from_str(&content)?,
+        _ => serde_yaml::from_str(&content)?,
+    };
+    Ok(config)
+}
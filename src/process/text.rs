@@ -1,8 +1,8 @@
-use super::process_genpass;
+use super::{process_genpass_with_rng, GenPassOptions};
 use crate::TextSignFormat;
 use anyhow::Result;
 use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
-use rand::rngs::OsRng;
+use rand::{rngs::OsRng, CryptoRng, RngCore};
 use std::{collections::HashMap, io::Read};
 
 // Blake3 和 Ed25519 都需要一个 sign 方法，所以可以抽取成 trait
@@ -64,9 +64,17 @@ pub fn process_text_verify(
 
 // pub fn process_text_generate(format: TextSignFormat) -> Result<Vec<Vec<u8>>> {
 pub fn process_text_generate(format: TextSignFormat) -> Result<HashMap<&'static str, Vec<u8>>> {
+    process_text_generate_with_rng(format, &mut OsRng)
+}
+
+/// Same as `process_text_generate` but with the caller's random number generator
+pub fn process_text_generate_with_rng<R: RngCore + CryptoRng>(
+    format: TextSignFormat,
+    rng: &mut R,
+) -> Result<HashMap<&'static str, Vec<u8>>> {
     match format {
-        TextSignFormat::Blake3 => Blake3::generate(),
-        TextSignFormat::Ed25519 => Ed25519Signer::generate(),
+        TextSignFormat::Blake3 => Blake3::generate(rng),
+        TextSignFormat::Ed25519 => Ed25519Signer::generate(rng),
     }
 }
 
@@ -135,8 +143,12 @@ impl Blake3 {
         Ok(signer)
     }
 
-    fn generate() -> Result<HashMap<&'static str, Vec<u8>>> {
-        let key = process_genpass(32, true, true, true, true)?;
+    pub fn generate<R: RngCore + CryptoRng>(rng: &mut R) -> Result<HashMap<&'static str, Vec<u8>>> {
+        let opts = GenPassOptions {
+            length: 32,
+            ..Default::default()
+        };
+        let key = process_genpass_with_rng(&opts, rng)?;
         let mut map = HashMap::new();
         map.insert("blake3.txt", key.as_bytes().to_vec());
         Ok(map)
@@ -156,9 +168,8 @@ impl Ed25519Signer {
         Ok(signer)
     }
 
-    fn generate() -> Result<HashMap<&'static str, Vec<u8>>> {
-        let mut csprng = OsRng;
-        let sk: SigningKey = SigningKey::generate(&mut csprng);
+    pub fn generate<R: RngCore + CryptoRng>(rng: &mut R) -> Result<HashMap<&'static str, Vec<u8>>> {
+        let sk: SigningKey = SigningKey::generate(rng);
         let pk: VerifyingKey = (&sk).into();
         let mut map = HashMap::new();
         map.insert("ed25519.sk", sk.to_bytes().to_vec());
@@ -210,4 +221,26 @@ mod tests {
         assert!(ret);
         Ok(())
     }
+
+    #[test]
+    fn test_process_text_generate_with_rng() -> Result<()> {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let format = TextSignFormat::Ed25519;
+        let a = process_text_generate_with_rng(format, &mut StdRng::seed_from_u64(42))?;
+        let b = process_text_generate_with_rng(format, &mut StdRng::seed_from_u64(42))?;
+        assert_eq!(a, b);
+
+        // 同一个种子生成的密钥签名结果也一样
+        let signer = Ed25519Signer::try_new(&a["ed25519.sk"])?;
+        let sig = signer.sign(&mut "hello".as_bytes())?;
+        let verifier = Ed25519Verifier::try_new(&b["ed25519.pk"])?;
+        assert!(verifier.verify(&mut "hello".as_bytes(), &sig)?);
+
+        let format = TextSignFormat::Blake3;
+        let a = process_text_generate_with_rng(format, &mut StdRng::seed_from_u64(7))?;
+        let b = process_text_generate_with_rng(format, &mut StdRng::seed_from_u64(7))?;
+        assert_eq!(a["blake3.txt"], b["blake3.txt"]);
+        Ok(())
+    }
 }
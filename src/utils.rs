@@ -1,5 +1,8 @@
 use anyhow::Result;
-use std::{fs::File, io::Read};
+use std::{
+    fs::File,
+    io::{BufWriter, Read, Write},
+};
 
 pub fn get_reader(input: &str) -> anyhow::Result<Box<dyn Read>> {
     let reader: Box<dyn Read> = if input == "-" {
@@ -11,6 +14,18 @@ pub fn get_reader(input: &str) -> anyhow::Result<Box<dyn Read>> {
     Ok(reader)
 }
 
+// 和 get_reader 对应，"-" 表示输出到 stdout
+// stdout 默认按行刷新，逐条输出记录时很慢，所以也包一层 BufWriter，用完要记得 flush
+pub fn get_writer(output: &str) -> anyhow::Result<Box<dyn Write>> {
+    let writer: Box<dyn Write> = if output == "-" {
+        Box::new(BufWriter::new(std::io::stdout().lock()))
+    } else {
+        Box::new(BufWriter::new(File::create(output)?))
+    };
+
+    Ok(writer)
+}
+
 pub fn get_content(input: &str) -> Result<Vec<u8>> {
     let mut reader = get_reader(input)?;
     let mut buf = Vec::new();
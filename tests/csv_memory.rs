@@ -0,0 +1,111 @@
+// 替换了全局分配器来统计内存，放在单独的集成测试里，不影响库里的其他测试
+use anyhow::Result;
+use rcli::{process_csv_stream, CsvDialect, CsvQuery, CsvTypes, OutputFormat};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    io::{self, Read, Write},
+};
+
+// 按线程统计堆内存的峰值，测试是多线程并行跑的，所以不能用全局计数
+struct TrackingAllocator;
+
+thread_local! {
+    static CURRENT: Cell<usize> = const { Cell::new(0) };
+    static PEAK: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for TrackingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = CURRENT.try_with(|c| {
+            let current = c.get() + layout.size();
+            c.set(current);
+            let _ = PEAK.try_with(|p| p.set(p.get().max(current)));
+        });
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let _ = CURRENT.try_with(|c| c.set(c.get().saturating_sub(layout.size())));
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: TrackingAllocator = TrackingAllocator;
+
+// 按需生成 CSV 内容的 reader，不会把整个文件放在内存里
+struct GeneratedCsv {
+    rows: usize,
+    current: usize,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl GeneratedCsv {
+    fn new(rows: usize) -> Self {
+        Self {
+            rows,
+            current: 0,
+            buf: b"Name,Position,DOB,Nationality,Kit Number\n".to_vec(),
+            pos: 0,
+        }
+    }
+}
+
+impl Read for GeneratedCsv {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.buf.len() {
+            if self.current == self.rows {
+                return Ok(0);
+            }
+            self.buf.clear();
+            self.pos = 0;
+            writeln!(
+                self.buf,
+                "Player {},Midfielder,\"Jan 1, 1990 (30)\",Italy,{}",
+                self.current,
+                self.current % 99
+            )?;
+            self.current += 1;
+        }
+        let n = out.len().min(self.buf.len() - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+fn peak_memory(rows: usize, format: OutputFormat) -> Result<usize> {
+    let mut reader = GeneratedCsv::new(rows);
+    let mut writer = io::sink();
+    PEAK.with(|p| p.set(CURRENT.with(|c| c.get())));
+    let base = PEAK.with(|p| p.get());
+    process_csv_stream(
+        &mut reader,
+        &mut writer,
+        format,
+        &CsvDialect::default(),
+        &CsvTypes::default(),
+        &CsvQuery::default(),
+    )?;
+    Ok(PEAK.with(|p| p.get()) - base)
+}
+
+#[test]
+fn test_process_csv_stream_constant_memory() -> Result<()> {
+    for format in [
+        OutputFormat::Json,
+        OutputFormat::Yaml,
+        OutputFormat::Toml,
+        OutputFormat::Ndjson,
+        OutputFormat::Xml,
+    ] {
+        let small = peak_memory(200, format)?;
+        let large = peak_memory(20_000, format)?;
+        // 行数多了 100 倍，峰值内存应该基本不变（只和单行大小有关）
+        assert!(large < 256 * 1024, "peak memory too high: {}", large);
+        assert!(large <= small + 4 * 1024, "{} vs {}", large, small);
+    }
+    Ok(())
+}